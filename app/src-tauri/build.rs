@@ -1,3 +1,6 @@
 fn main() {
-    tauri_build::build()
+    // Only the app needs its Tauri context generated, the player library builds without it.
+    if std::env::var_os("CARGO_FEATURE_TAURI").is_some() {
+        tauri_build::build()
+    }
 }
@@ -0,0 +1,95 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    path::PathBuf,
+    time::SystemTime,
+};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+/// Subdirectory of the cache that holds seek indexes.
+pub const SEEK_INDEX: &str = "seek-index";
+
+/// Subdirectory of the cache that holds measured track loudness.
+pub const LOUDNESS: &str = "loudness";
+
+/// Subdirectory of the cache that holds waveform peaks.
+pub const WAVEFORM: &str = "waveform";
+
+/// Identifies one version of a file on disk. An entry is only used while the path, modification
+/// time and size all still match, so editing or replacing a file invalidates its cached data.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct CacheKey {
+    path: String,
+    modified: SystemTime,
+    size: u64,
+}
+
+impl CacheKey {
+    /// Returns `None` when the platform can't report a modification time, since a cached entry
+    /// couldn't be told apart from a stale one.
+    pub fn new(path: &str, metadata: &std::fs::Metadata) -> Option<Self> {
+        Some(Self {
+            path: path.to_string(),
+            modified: metadata.modified().ok()?,
+            size: metadata.len(),
+        })
+    }
+
+    /// Entries are named after the path alone, so a stale entry gets overwritten instead of
+    /// piling up next to the fresh one.
+    fn file_name(&self) -> String {
+        let mut hasher = DefaultHasher::new();
+        self.path.hash(&mut hasher);
+        format!("{:016x}.json", hasher.finish())
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheEntry<T> {
+    key: CacheKey,
+    value: T,
+}
+
+/// The cache lives in the user's cache directory, e.g. `~/.cache` on Linux, so it isn't shared
+/// with other users of the machine and survives the temp directory being cleaned. Platforms
+/// without one fall back to the temp directory.
+fn cache_dir(kind: &str) -> PathBuf {
+    dirs::cache_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join("music-box-cache")
+        .join(kind)
+}
+
+/// Reads a cached value, or `None` if there is no entry for the current version of the file.
+pub async fn read<T: DeserializeOwned>(kind: &str, key: &CacheKey) -> Option<T> {
+    let json = tokio::fs::read(cache_dir(kind).join(key.file_name())).await.ok()?;
+    let entry: CacheEntry<T> = serde_json::from_slice(&json).ok()?;
+
+    match entry.key == *key {
+        true => Some(entry.value),
+        false => None,
+    }
+}
+
+/// Stores a value for the current version of the file. Caching is best effort, so failures are
+/// ignored and the value just gets computed again next time.
+pub async fn write<T: Serialize>(kind: &str, key: &CacheKey, value: &T) {
+    let dir = cache_dir(kind);
+    if tokio::fs::create_dir_all(&dir).await.is_err() {
+        return;
+    }
+
+    let entry = CacheEntry { key: key.clone(), value };
+    if let Ok(json) = serde_json::to_vec(&entry) {
+        let _ = tokio::fs::write(dir.join(key.file_name()), json).await;
+    }
+}
+
+/// Removes every cached entry of one kind.
+pub async fn clear(kind: &str) -> std::io::Result<()> {
+    match tokio::fs::remove_dir_all(cache_dir(kind)).await {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,269 @@
+use std::{io::BufReader, time::Duration};
+
+use rodio::{source::SeekError, Decoder, Source};
+#[cfg(feature = "symphonia")]
+use symphonia::core::{
+    audio::{Channels, SampleBuffer, SignalSpec},
+    codecs::DecoderOptions,
+    errors::Error,
+    formats::{FormatOptions, FormatReader, SeekMode, SeekTo},
+    io::{MediaSource, MediaSourceStream},
+    meta::MetadataOptions,
+    probe::Hint,
+    units::Time,
+};
+
+use crate::media::MediaHandle;
+use crate::player::{DecoderBackend, PlayerError, PlayerResult};
+use crate::trace;
+
+/// How many packets in a row Symphonia may fail to decode before the stream counts as broken.
+#[cfg(feature = "symphonia")]
+const MAX_DECODE_RETRIES: usize = 3;
+
+/// Decodes audio with whichever `DecoderBackend` the player was configured with.
+pub enum AudioDecoder {
+    /// Boxed since it is several times the size of the Symphonia one.
+    Rodio(Box<Decoder<BufReader<MediaHandle>>>),
+    #[cfg(feature = "symphonia")]
+    Symphonia(SymphoniaDecoder),
+}
+
+/// Runs `$body` with `$decoder` bound to the decoder of whichever backend `$self` holds.
+macro_rules! with_decoder {
+    ($self:expr, $decoder:ident => $body:expr) => {
+        match $self {
+            AudioDecoder::Rodio($decoder) => $body,
+            #[cfg(feature = "symphonia")]
+            AudioDecoder::Symphonia($decoder) => $body,
+        }
+    };
+}
+
+/// What the decoders of a track are opened with. Sources keep a copy to open another decoder
+/// whenever they start over.
+#[derive(Clone, Copy)]
+pub struct DecoderSettings {
+    pub backend: DecoderBackend,
+    /// The buffer size of rodio's decoders. Symphonia buffers its reads on its own.
+    pub read_buffer_size: usize,
+    /// Whether times in the track are reached by seeking the decoder, which can only be done from
+    /// the start of the file, rather than by starting to decode at a byte offset.
+    pub seeks: bool,
+}
+
+impl AudioDecoder {
+    /// Starts decoding `media` from its current position.
+    pub fn new(media: MediaHandle, settings: DecoderSettings) -> PlayerResult<Self> {
+        match settings.backend {
+            DecoderBackend::Rodio => Decoder::new(BufReader::with_capacity(settings.read_buffer_size, media))
+                .map(|decoder| Self::Rodio(Box::new(decoder)))
+                .map_err(|error| {
+                    trace::warning!("couldn't decode", error = error);
+                    PlayerError::UnableToDecodeFile(error.to_string())
+                }),
+            #[cfg(feature = "symphonia")]
+            DecoderBackend::Symphonia => SymphoniaDecoder::new(media).map(Self::Symphonia).map_err(|error| {
+                trace::warning!("couldn't decode with symphonia", error = error);
+                PlayerError::UnableToDecodeFile(error.to_string())
+            }),
+        }
+    }
+
+    /// Starts decoding `media` from its current position, which is `time` into the track. When
+    /// the settings say to seek the decoder, `media` is at the start of the file instead and the
+    /// decoder is seeked to `time`.
+    pub fn open_at(media: MediaHandle, settings: DecoderSettings, time: Duration) -> PlayerResult<Self> {
+        let mut decoder = Self::new(media, settings)?;
+        if settings.seeks && !time.is_zero() {
+            decoder.try_seek(time).map_err(|error| {
+                trace::warning!("couldn't seek the decoder", time = time, error = error);
+                PlayerError::NotAbleToSeek(std::io::ErrorKind::Unsupported)
+            })?;
+        }
+
+        Ok(decoder)
+    }
+}
+
+impl Iterator for AudioDecoder {
+    type Item = i16;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        with_decoder!(self, decoder => decoder.next())
+    }
+}
+
+impl Source for AudioDecoder {
+    fn current_frame_len(&self) -> Option<usize> {
+        with_decoder!(self, decoder => decoder.current_frame_len())
+    }
+
+    fn channels(&self) -> u16 {
+        with_decoder!(self, decoder => decoder.channels())
+    }
+
+    fn sample_rate(&self) -> u32 {
+        with_decoder!(self, decoder => decoder.sample_rate())
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        with_decoder!(self, decoder => decoder.total_duration())
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        with_decoder!(self, decoder => decoder.try_seek(pos))
+    }
+}
+
+#[cfg(feature = "symphonia")]
+impl MediaSource for MediaHandle {
+    fn is_seekable(&self) -> bool {
+        true
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        match self {
+            MediaHandle::File(file) => file.metadata().ok().map(|metadata| metadata.len()),
+            MediaHandle::Memory { data, .. } => Some(data.len() as u64),
+        }
+    }
+}
+
+/// Decodes with Symphonia, which can read more containers and codecs than rodio's own decoders,
+/// like AAC in M4A files, and seeks by asking the demuxer for the exact sample.
+#[cfg(feature = "symphonia")]
+pub struct SymphoniaDecoder {
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn symphonia::core::codecs::Decoder>,
+    track_id: u32,
+    spec: SignalSpec,
+    /// The interleaved samples of the last decoded packet.
+    buffer: SampleBuffer<i16>,
+    /// How many samples of `buffer` have been handed on.
+    position: usize,
+    total_duration: Option<Duration>,
+}
+
+#[cfg(feature = "symphonia")]
+impl SymphoniaDecoder {
+    pub fn new(media: MediaHandle) -> Result<Self, Error> {
+        let stream = MediaSourceStream::new(Box::new(media), Default::default());
+        let options = FormatOptions {
+            enable_gapless: true,
+            ..Default::default()
+        };
+        let probed = symphonia::default::get_probe().format(&Hint::new(), stream, &options, &MetadataOptions::default())?;
+
+        let track = probed.format.default_track().ok_or(Error::Unsupported("no audio track"))?;
+        let track_id = track.id;
+        let decoder = symphonia::default::get_codecs().make(&track.codec_params, &DecoderOptions::default())?;
+        let total_duration = track
+            .codec_params
+            .time_base
+            .zip(track.codec_params.n_frames)
+            .map(|(time_base, frames)| Self::time_duration(time_base.calc_time(frames)));
+
+        let mut source = Self {
+            track_id,
+            format: probed.format,
+            decoder,
+            spec: SignalSpec::new(0, Channels::empty()),
+            buffer: SampleBuffer::new(0, SignalSpec::new(0, Channels::empty())),
+            position: 0,
+            total_duration,
+        };
+        // The sample rate and channels are only known for sure once a packet has been decoded.
+        source.decode_next()?;
+
+        Ok(source)
+    }
+
+    /// Decodes the next packet of the track into the buffer, and returns the timestamp the
+    /// packet starts at along with how many frames it holds.
+    fn decode_next(&mut self) -> Result<(u64, u64), Error> {
+        let mut errors = 0;
+        loop {
+            let packet = self.format.next_packet()?;
+            if packet.track_id() != self.track_id {
+                continue;
+            }
+
+            match self.decoder.decode(&packet) {
+                Ok(decoded) => {
+                    self.spec = *decoded.spec();
+                    self.buffer = SampleBuffer::new(decoded.capacity() as u64, self.spec);
+                    self.buffer.copy_interleaved_ref(decoded);
+                    self.position = 0;
+                    return Ok((packet.ts(), packet.dur()));
+                }
+                // A broken packet is skipped over, as long as the ones after it can be decoded.
+                Err(Error::DecodeError(_)) if errors < MAX_DECODE_RETRIES => errors += 1,
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    fn time_duration(time: Time) -> Duration {
+        Duration::from_secs(time.seconds).saturating_add(Duration::from_secs_f64(time.frac))
+    }
+}
+
+#[cfg(feature = "symphonia")]
+impl Iterator for SymphoniaDecoder {
+    type Item = i16;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.position >= self.buffer.len() {
+            self.decode_next().ok()?;
+        }
+
+        let sample = self.buffer.samples()[self.position];
+        self.position += 1;
+        Some(sample)
+    }
+}
+
+#[cfg(feature = "symphonia")]
+impl Source for SymphoniaDecoder {
+    fn current_frame_len(&self) -> Option<usize> {
+        Some(self.buffer.len())
+    }
+
+    fn channels(&self) -> u16 {
+        self.spec.channels.count() as u16
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.spec.rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.total_duration
+    }
+
+    /// Lands on the exact sample at `pos`. The demuxer can only seek to the start of a packet, so
+    /// the packets before the one `pos` is in are decoded and dropped, and so is the start of it.
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        let seeked = self
+            .format
+            .seek(
+                SeekMode::Accurate,
+                SeekTo::Time {
+                    time: Time::from(pos.as_secs_f64()),
+                    track_id: Some(self.track_id),
+                },
+            )
+            .map_err(|error| SeekError::Other(Box::new(error)))?;
+        self.decoder.reset();
+
+        loop {
+            let (start, frames) = self.decode_next().map_err(|error| SeekError::Other(Box::new(error)))?;
+            if start + frames > seeked.required_ts {
+                let channels = self.spec.channels.count();
+                self.position = (seeked.required_ts.saturating_sub(start) as usize * channels).min(self.buffer.len());
+                return Ok(());
+            }
+        }
+    }
+}
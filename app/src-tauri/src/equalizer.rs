@@ -0,0 +1,160 @@
+use std::{
+    f32::consts::PI,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, PoisonError,
+    },
+};
+
+use serde::{Deserialize, Serialize};
+
+/// How wide each band is. A Q of 1 spans about an octave and a third, which leaves the default
+/// bands overlapping just enough to give a smooth curve.
+const BAND_Q: f32 = 1.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct EqualizerBand {
+    /// The centre of the band, in Hz.
+    pub frequency: f32,
+    /// How much the band is boosted, or cut when negative, in dB.
+    pub gain_db: f32,
+}
+
+/// A set of bands that boost or cut the audio around their frequencies. The default has five flat
+/// bands at 60, 230, 910, 3600 and 14000 Hz.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Equalizer {
+    pub bands: Vec<EqualizerBand>,
+}
+
+impl Default for Equalizer {
+    fn default() -> Self {
+        Self {
+            bands: [60.0, 230.0, 910.0, 3600.0, 14000.0]
+                .into_iter()
+                .map(|frequency| EqualizerBand { frequency, gain_db: 0.0 })
+                .collect(),
+        }
+    }
+}
+
+impl Equalizer {
+    /// Whether every band is at 0 dB, in which case the audio is passed through untouched.
+    pub fn is_flat(&self) -> bool {
+        self.bands.iter().all(|band| band.gain_db == 0.0)
+    }
+}
+
+/// The equalizer of a player, shared with the sources that apply it. The generation is bumped
+/// on every change so the sources can notice without taking the lock for every sample.
+pub struct SharedEqualizer {
+    equalizer: Mutex<Equalizer>,
+    generation: AtomicU64,
+}
+
+impl SharedEqualizer {
+    pub fn new(equalizer: Equalizer) -> Self {
+        Self {
+            equalizer: Mutex::new(equalizer),
+            generation: AtomicU64::new(0),
+        }
+    }
+
+    pub fn get(&self) -> Equalizer {
+        self.equalizer.lock().unwrap_or_else(PoisonError::into_inner).clone()
+    }
+
+    pub fn set(&self, equalizer: Equalizer) {
+        *self.equalizer.lock().unwrap_or_else(PoisonError::into_inner) = equalizer;
+        self.generation.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::SeqCst)
+    }
+}
+
+/// A peaking filter from the RBJ audio EQ cookbook, with coefficients normalised by `a0`.
+struct Biquad {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+}
+
+impl Biquad {
+    fn peaking(band: &EqualizerBand, sample_rate: u32) -> Self {
+        let amplitude = 10f32.powf(band.gain_db / 40.0);
+        let w0 = 2.0 * PI * band.frequency / sample_rate as f32;
+        let alpha = w0.sin() / (2.0 * BAND_Q);
+        let a0 = 1.0 + alpha / amplitude;
+
+        Self {
+            b0: (1.0 + alpha * amplitude) / a0,
+            b1: -2.0 * w0.cos() / a0,
+            b2: (1.0 - alpha * amplitude) / a0,
+            a1: -2.0 * w0.cos() / a0,
+            a2: (1.0 - alpha / amplitude) / a0,
+        }
+    }
+}
+
+/// The last two inputs and outputs of one filter on one channel.
+#[derive(Default, Clone)]
+struct BiquadHistory {
+    x1: f32,
+    x2: f32,
+    y1: f32,
+    y2: f32,
+}
+
+/// The filters for an equalizer at one sample rate, with separate history for every channel.
+pub struct EqualizerFilters {
+    filters: Vec<Biquad>,
+    /// Indexed by channel, then by filter.
+    history: Vec<Vec<BiquadHistory>>,
+}
+
+impl EqualizerFilters {
+    /// Returns `None` when no band would change the audio, so it can be passed through as is.
+    /// Flat bands and ones above the Nyquist frequency are left out.
+    pub fn new(equalizer: &Equalizer, sample_rate: u32, channels: u16) -> Option<Self> {
+        let nyquist = sample_rate as f32 / 2.0;
+        let filters: Vec<Biquad> = equalizer
+            .bands
+            .iter()
+            .filter(|band| band.gain_db != 0.0 && band.frequency > 0.0 && band.frequency < nyquist)
+            .map(|band| Biquad::peaking(band, sample_rate))
+            .collect();
+
+        if filters.is_empty() {
+            return None;
+        }
+
+        let history = vec![vec![BiquadHistory::default(); filters.len()]; channels as usize];
+        Some(Self { filters, history })
+    }
+
+    pub fn process(&mut self, channel: usize, sample: f32) -> f32 {
+        let history = match self.history.get_mut(channel) {
+            Some(history) => history,
+            None => return sample,
+        };
+
+        let mut value = sample;
+        for (filter, state) in self.filters.iter().zip(history.iter_mut()) {
+            let output = filter.b0 * value + filter.b1 * state.x1 + filter.b2 * state.x2
+                - filter.a1 * state.y1
+                - filter.a2 * state.y2;
+
+            state.x2 = state.x1;
+            state.x1 = value;
+            state.y2 = state.y1;
+            state.y1 = output;
+            value = output;
+        }
+
+        value
+    }
+}
@@ -0,0 +1,81 @@
+//! Audio files generated for the tests, so no binary fixtures have to be checked in.
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::player::Player;
+
+/// An MPEG-1 layer III frame header for 128 kbps, 44.1 kHz stereo without CRC or padding.
+const MP3_HEADER: [u8; 4] = [0xFF, 0xFB, 0x90, 0x00];
+
+/// The length of a frame with `MP3_HEADER`, which is `144 * 128000 / 44100` rounded down.
+const MP3_FRAME_LEN: usize = 417;
+
+/// How long one frame of the fixture plays for.
+pub const MP3_FRAME_DURATION: Duration = Duration::from_nanos(1152 * 1_000_000_000 / 44100);
+
+/// Writes an MP3 of about `seconds` of silence to a file of its own in the temp directory and
+/// returns its path. Every frame has zeroed side info and main data, which decodes to silence.
+pub fn silent_mp3(name: &str, seconds: u32) -> PathBuf {
+    let frames = (Duration::from_secs(u64::from(seconds)).as_nanos() / MP3_FRAME_DURATION.as_nanos()) as usize;
+    let mut data = Vec::with_capacity(frames * MP3_FRAME_LEN);
+    for _ in 0..frames {
+        data.extend_from_slice(&MP3_HEADER);
+        data.resize(data.len() + MP3_FRAME_LEN - MP3_HEADER.len(), 0);
+    }
+
+    write(&format!("{name}.mp3"), &data)
+}
+
+/// Writes a mono 16-bit WAV at `sample_rate` with a second of silence, then `seconds` of a loud
+/// square wave, then another second of silence, for tests that need something audible.
+pub fn tone_wav(name: &str, seconds: u32, sample_rate: u32) -> PathBuf {
+    let silence = vec![0i16; sample_rate as usize];
+    let tone = (0..sample_rate * seconds).map(|index| match index / 20 % 2 {
+        0 => 16000i16,
+        _ => -16000,
+    });
+    let samples: Vec<i16> = silence.iter().copied().chain(tone).chain(silence.iter().copied()).collect();
+
+    let data_len = samples.len() as u32 * 2;
+    let mut data = Vec::with_capacity(44 + data_len as usize);
+    data.extend_from_slice(b"RIFF");
+    data.extend_from_slice(&(36 + data_len).to_le_bytes());
+    data.extend_from_slice(b"WAVEfmt ");
+    data.extend_from_slice(&16u32.to_le_bytes());
+    // PCM, one channel, the byte rate, the block align and the bits per sample.
+    data.extend_from_slice(&1u16.to_le_bytes());
+    data.extend_from_slice(&1u16.to_le_bytes());
+    data.extend_from_slice(&sample_rate.to_le_bytes());
+    data.extend_from_slice(&(sample_rate * 2).to_le_bytes());
+    data.extend_from_slice(&2u16.to_le_bytes());
+    data.extend_from_slice(&16u16.to_le_bytes());
+    data.extend_from_slice(b"data");
+    data.extend_from_slice(&data_len.to_le_bytes());
+    for sample in samples {
+        data.extend_from_slice(&sample.to_le_bytes());
+    }
+
+    write(&format!("{name}.wav"), &data)
+}
+
+/// Writes `data` to a file in the temp directory that no other test process uses.
+pub fn write(name: &str, data: &[u8]) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("music-box-tests-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(name);
+    std::fs::write(&path, data).unwrap();
+    path
+}
+
+/// A player on the default output device, or `None` on machines without one, where the tests
+/// that need to play something are skipped.
+pub fn output_player() -> Option<Player> {
+    match Player::new() {
+        Ok(player) => Some(player),
+        Err(error) => {
+            eprintln!("skipping, no output device: {error}");
+            None
+        }
+    }
+}
@@ -0,0 +1,370 @@
+use std::future::Future;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::Thread;
+use std::time::Duration;
+
+use tokio::sync::{mpsc, oneshot};
+
+use crate::player::{
+    AudioPlayer, FinishedCallback, HandedOverTrack, PlaybackState, Player, PlayerError, PlayerResult, PreparedTrack,
+    TrackLoader,
+};
+
+/// A message to the thread that owns a player, along with where to send the result. Each one
+/// does what the `Player` method of the same name does.
+pub enum PlayerCommand {
+    Play(oneshot::Sender<PlayerResult<()>>),
+    Pause(oneshot::Sender<()>),
+    Stop(oneshot::Sender<()>),
+    Restart(oneshot::Sender<PlayerResult<()>>),
+    Seek(Duration, oneshot::Sender<PlayerResult<()>>),
+    Volume(oneshot::Sender<f32>),
+    SetVolume(f32, oneshot::Sender<()>),
+    SetMasterVolume(f32, oneshot::Sender<()>),
+    FadeIn(Duration, oneshot::Sender<PlayerResult<()>>),
+    /// Fades out over the duration, and pauses at the end if the flag is set.
+    FadeOut(Duration, bool, oneshot::Sender<()>),
+    Elapsed(oneshot::Sender<PlayerResult<Duration>>),
+    TrackDuration(oneshot::Sender<Option<Duration>>),
+    State(oneshot::Sender<PlaybackState>),
+    Path(oneshot::Sender<Option<String>>),
+    OnFinished(FinishedCallback, oneshot::Sender<()>),
+    /// Loads the file at the path, like `Player::load_path()`.
+    Load(String, oneshot::Sender<PlayerResult<()>>),
+    TrackLoader(oneshot::Sender<TrackLoader>),
+    LoadPrepared(Box<PreparedTrack>, oneshot::Sender<PlayerResult<()>>),
+    HandOverTrack(oneshot::Sender<PlayerResult<Box<HandedOverTrack>>>),
+    TakeOverTrack(Box<HandedOverTrack>, oneshot::Sender<PlayerResult<()>>),
+}
+
+/// Controls a player that runs on a thread of its own. Handles are cheap to clone and can be
+/// shared between threads, and each command waits for the player thread to carry it out, so
+/// nothing waits on a lock while another caller's seek or load is busy with the file. Commands
+/// run one at a time, in the order they were sent.
+///
+/// `Music` keeps its players as handles, driving them through `AudioPlayer`, whose methods block
+/// instead of being async. Those that don't return anything are only queued, so the lock `Music`
+/// holds around a handle is released without waiting for the player to get to them.
+///
+/// The player thread keeps going until every handle to it is dropped. Once it has stopped, e.g.
+/// because a command panicked, every call returns `PlayerError::PlayerThreadStopped`, and the
+/// `AudioPlayer` queries report a silent player with nothing loaded.
+#[derive(Clone)]
+pub struct PlayerHandle {
+    commands: mpsc::UnboundedSender<PlayerCommand>,
+}
+
+impl PlayerHandle {
+    /// Moves `player` onto a new thread and returns a handle to control it with.
+    pub fn spawn(player: Player) -> Self {
+        let (commands, receiver) = mpsc::unbounded_channel();
+        std::thread::spawn(move || Self::run(player, receiver));
+
+        Self { commands }
+    }
+
+    fn run(mut player: Player, mut receiver: mpsc::UnboundedReceiver<PlayerCommand>) {
+        // Loading reads the file through tokio, so the thread needs a runtime of its own.
+        let runtime = match tokio::runtime::Builder::new_current_thread().enable_all().build() {
+            Ok(runtime) => runtime,
+            Err(_) => return,
+        };
+
+        // A caller that stopped waiting for its result doesn't matter to the player, so failed
+        // replies are ignored.
+        runtime.block_on(async {
+            while let Some(command) = receiver.recv().await {
+                match command {
+                    PlayerCommand::Play(reply) => {
+                        let _ = reply.send(player.play());
+                    }
+                    PlayerCommand::Pause(reply) => {
+                        player.pause();
+                        let _ = reply.send(());
+                    }
+                    PlayerCommand::Stop(reply) => {
+                        player.stop();
+                        let _ = reply.send(());
+                    }
+                    PlayerCommand::Restart(reply) => {
+                        let _ = reply.send(player.restart());
+                    }
+                    PlayerCommand::Seek(time_offset, reply) => {
+                        let _ = reply.send(player.seek(time_offset));
+                    }
+                    PlayerCommand::Volume(reply) => {
+                        let _ = reply.send(player.volume());
+                    }
+                    PlayerCommand::SetVolume(volume, reply) => {
+                        player.set_volume(volume);
+                        let _ = reply.send(());
+                    }
+                    PlayerCommand::SetMasterVolume(volume, reply) => {
+                        player.set_master_volume(volume);
+                        let _ = reply.send(());
+                    }
+                    PlayerCommand::FadeIn(duration, reply) => {
+                        let _ = reply.send(player.fade_in(duration));
+                    }
+                    PlayerCommand::FadeOut(duration, pause, reply) => {
+                        player.fade_out(duration, pause);
+                        let _ = reply.send(());
+                    }
+                    PlayerCommand::Elapsed(reply) => {
+                        let _ = reply.send(player.elapsed());
+                    }
+                    PlayerCommand::TrackDuration(reply) => {
+                        let _ = reply.send(player.duration());
+                    }
+                    PlayerCommand::State(reply) => {
+                        let _ = reply.send(player.state());
+                    }
+                    PlayerCommand::Path(reply) => {
+                        let _ = reply.send(player.path().map(String::from));
+                    }
+                    PlayerCommand::OnFinished(callback, reply) => {
+                        player.on_finished(callback);
+                        let _ = reply.send(());
+                    }
+                    PlayerCommand::Load(path, reply) => {
+                        let _ = reply.send(player.load_path(&path).await);
+                    }
+                    PlayerCommand::TrackLoader(reply) => {
+                        let _ = reply.send(player.track_loader());
+                    }
+                    PlayerCommand::LoadPrepared(prepared, reply) => {
+                        let _ = reply.send(player.load_prepared(*prepared));
+                    }
+                    PlayerCommand::HandOverTrack(reply) => {
+                        let _ = reply.send(player.hand_over_track().map(Box::new));
+                    }
+                    PlayerCommand::TakeOverTrack(handed_over, reply) => {
+                        let _ = reply.send(player.take_over_track(*handed_over));
+                    }
+                }
+            }
+        });
+    }
+
+    /// Sends the command made by `command` and waits for the player thread to reply.
+    async fn send<T>(&self, command: impl FnOnce(oneshot::Sender<T>) -> PlayerCommand) -> PlayerResult<T> {
+        let (reply, result) = oneshot::channel();
+        self.commands
+            .send(command(reply))
+            .map_err(|_| PlayerError::PlayerThreadStopped)?;
+
+        result.await.map_err(|_| PlayerError::PlayerThreadStopped)
+    }
+
+    /// Like `send()`, but blocks the calling thread until the player thread replies. Unlike
+    /// tokio's own blocking receive this may be called from inside a runtime, which is what a
+    /// lock would do too.
+    fn send_blocking<T>(&self, command: impl FnOnce(oneshot::Sender<T>) -> PlayerCommand) -> PlayerResult<T> {
+        block_on(self.send(command))
+    }
+
+    /// Sends the command made by `command` without waiting for the player thread to carry it out.
+    /// Commands run in the order they were sent, so anything sent after it sees its effect.
+    fn post<T>(&self, command: impl FnOnce(oneshot::Sender<T>) -> PlayerCommand) {
+        let (reply, _) = oneshot::channel();
+        let _ = self.commands.send(command(reply));
+    }
+
+    pub async fn play(&self) -> PlayerResult<()> {
+        self.send(PlayerCommand::Play).await?
+    }
+
+    pub async fn pause(&self) -> PlayerResult<()> {
+        self.send(PlayerCommand::Pause).await
+    }
+
+    pub async fn stop(&self) -> PlayerResult<()> {
+        self.send(PlayerCommand::Stop).await
+    }
+
+    pub async fn restart(&self) -> PlayerResult<()> {
+        self.send(PlayerCommand::Restart).await?
+    }
+
+    pub async fn seek(&self, time_offset: Duration) -> PlayerResult<()> {
+        self.send(|reply| PlayerCommand::Seek(time_offset, reply)).await?
+    }
+
+    pub async fn volume(&self) -> PlayerResult<f32> {
+        self.send(PlayerCommand::Volume).await
+    }
+
+    pub async fn set_volume(&self, volume: f32) -> PlayerResult<()> {
+        self.send(|reply| PlayerCommand::SetVolume(volume, reply)).await
+    }
+
+    pub async fn set_master_volume(&self, volume: f32) -> PlayerResult<()> {
+        self.send(|reply| PlayerCommand::SetMasterVolume(volume, reply)).await
+    }
+
+    pub async fn fade_in(&self, duration: Duration) -> PlayerResult<()> {
+        self.send(|reply| PlayerCommand::FadeIn(duration, reply)).await?
+    }
+
+    pub async fn fade_out(&self, duration: Duration, pause: bool) -> PlayerResult<()> {
+        self.send(|reply| PlayerCommand::FadeOut(duration, pause, reply)).await
+    }
+
+    pub async fn elapsed(&self) -> PlayerResult<Duration> {
+        self.send(PlayerCommand::Elapsed).await?
+    }
+
+    pub async fn duration(&self) -> PlayerResult<Option<Duration>> {
+        self.send(PlayerCommand::TrackDuration).await
+    }
+
+    pub async fn state(&self) -> PlayerResult<PlaybackState> {
+        self.send(PlayerCommand::State).await
+    }
+
+    pub async fn path(&self) -> PlayerResult<Option<String>> {
+        self.send(PlayerCommand::Path).await
+    }
+
+    pub async fn on_finished(&self, callback: FinishedCallback) -> PlayerResult<()> {
+        self.send(|reply| PlayerCommand::OnFinished(callback, reply)).await
+    }
+
+    pub async fn load_path(&self, path: &str) -> PlayerResult<()> {
+        let path = path.to_string();
+        self.send(|reply| PlayerCommand::Load(path, reply)).await?
+    }
+
+    pub async fn track_loader(&self) -> PlayerResult<TrackLoader> {
+        self.send(PlayerCommand::TrackLoader).await
+    }
+
+    pub async fn load_prepared(&self, prepared: PreparedTrack) -> PlayerResult<()> {
+        self.send(|reply| PlayerCommand::LoadPrepared(Box::new(prepared), reply))
+            .await?
+    }
+
+    pub async fn hand_over_track(&self) -> PlayerResult<HandedOverTrack> {
+        self.send(PlayerCommand::HandOverTrack).await?.map(|handed_over| *handed_over)
+    }
+
+    pub async fn take_over_track(&self, handed_over: HandedOverTrack) -> PlayerResult<()> {
+        self.send(|reply| PlayerCommand::TakeOverTrack(Box::new(handed_over), reply))
+            .await?
+    }
+}
+
+impl AudioPlayer for PlayerHandle {
+    fn play(&mut self) -> PlayerResult<()> {
+        self.send_blocking(PlayerCommand::Play)?
+    }
+
+    fn pause(&mut self) {
+        self.post(PlayerCommand::Pause);
+    }
+
+    fn stop(&mut self) {
+        self.post(PlayerCommand::Stop);
+    }
+
+    fn seek(&mut self, time_offset: Duration) -> PlayerResult<()> {
+        self.send_blocking(|reply| PlayerCommand::Seek(time_offset, reply))?
+    }
+
+    fn volume(&self) -> f32 {
+        self.send_blocking(PlayerCommand::Volume).unwrap_or(0.0)
+    }
+
+    fn set_volume(&mut self, volume: f32) {
+        self.post(|reply| PlayerCommand::SetVolume(volume, reply));
+    }
+
+    fn set_master_volume(&mut self, volume: f32) {
+        self.post(|reply| PlayerCommand::SetMasterVolume(volume, reply));
+    }
+
+    fn fade_in(&mut self, duration: Duration) -> PlayerResult<()> {
+        self.send_blocking(|reply| PlayerCommand::FadeIn(duration, reply))?
+    }
+
+    fn fade_out(&mut self, duration: Duration, pause: bool) {
+        self.post(|reply| PlayerCommand::FadeOut(duration, pause, reply));
+    }
+
+    fn elapsed(&self) -> PlayerResult<Duration> {
+        self.send_blocking(PlayerCommand::Elapsed)?
+    }
+
+    fn duration(&self) -> Option<Duration> {
+        self.send_blocking(PlayerCommand::TrackDuration).unwrap_or(None)
+    }
+
+    fn state(&self) -> PlaybackState {
+        self.send_blocking(PlayerCommand::State).unwrap_or(PlaybackState::Empty)
+    }
+}
+
+/// Wakes a thread parked in `block_on()`.
+struct Unpark(Thread);
+
+impl Wake for Unpark {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Runs `future` to completion on the calling thread, parking it whenever the future is waiting.
+/// `Music` drives handles this way where its own methods aren't async.
+pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+    let waker = Waker::from(Arc::new(Unpark(std::thread::current())));
+    let mut context = Context::from_waker(&waker);
+    let mut future = std::pin::pin!(future);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+            return output;
+        }
+        std::thread::park();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures;
+
+    #[tokio::test]
+    async fn commands_are_carried_out_on_the_player_thread() {
+        let Some(player) = fixtures::output_player() else {
+            return;
+        };
+        let handle = PlayerHandle::spawn(player);
+        assert_eq!(handle.state().await, Ok(PlaybackState::Empty));
+
+        let path = fixtures::silent_mp3("handle", 2);
+        handle.pause().await.unwrap();
+        handle.load_path(path.to_str().unwrap()).await.unwrap();
+        assert_eq!(handle.state().await, Ok(PlaybackState::Paused));
+
+        let missing = handle.load_path("no such file.mp3").await;
+        assert!(matches!(missing, Err(PlayerError::UnableToOpenFile { .. })), "{missing:?}");
+    }
+
+    #[tokio::test]
+    async fn queued_commands_are_seen_by_later_queries() {
+        let Some(player) = fixtures::output_player() else {
+            return;
+        };
+        let mut handle = PlayerHandle::spawn(player);
+
+        // These block on the player thread from inside the test's runtime.
+        AudioPlayer::set_volume(&mut handle, 0.25);
+        assert_eq!(AudioPlayer::volume(&handle), 0.25);
+        assert_eq!(
+            AudioPlayer::seek(&mut handle, Duration::from_secs(1)),
+            Err(PlayerError::NoSeekIndex)
+        );
+
+        assert_eq!(handle.volume().await, Ok(0.25));
+    }
+}
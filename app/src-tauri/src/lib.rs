@@ -0,0 +1,16 @@
+pub mod cache;
+#[cfg(feature = "tauri")]
+pub mod commands;
+pub mod decoder;
+pub mod equalizer;
+#[cfg(test)]
+mod fixtures;
+pub mod handle;
+pub mod media;
+pub mod metadata;
+pub mod mock;
+pub mod mp3;
+pub mod music;
+pub mod player;
+pub mod sources;
+pub mod trace;
@@ -1,10 +1,6 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
-pub mod commands;
-pub mod music;
-pub mod player;
-
 fn main() {
     tauri::Builder::default()
         .plugin(tauri_plugin_shell::init())
@@ -0,0 +1,142 @@
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+/// The extensions of the formats rodio's decoders can play.
+pub const SUPPORTED_FORMATS: &[&str] = &["mp3", "flac", "ogg", "wav"];
+
+/// The extensions of the formats Symphonia can play.
+#[cfg(feature = "symphonia")]
+pub const SYMPHONIA_FORMATS: &[&str] = &["mp3", "flac", "ogg", "wav", "m4a", "aac"];
+
+/// How many bytes from the start of a file `sniff_format()` looks at.
+const SNIFF_LEN: u64 = 12;
+
+/// Recognises which of the formats either decoder backend can play a file is in from its first
+/// bytes, and returns its extension. MP3s without an ID3 tag are recognised by the sync word of
+/// their first frame, and raw AAC by that of its first ADTS header, which has the layer bits an
+/// MP3 frame can't have.
+pub fn sniff_format(header: &[u8]) -> Option<&'static str> {
+    match header {
+        [b'I', b'D', b'3', ..] => Some("mp3"),
+        [0xFF, second, ..] if second & 0xF6 == 0xF0 => Some("aac"),
+        [0xFF, second, ..] if second & 0xE0 == 0xE0 => Some("mp3"),
+        [b'f', b'L', b'a', b'C', ..] => Some("flac"),
+        [b'O', b'g', b'g', b'S', ..] => Some("ogg"),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'A', b'V', b'E', ..] => Some("wav"),
+        [_, _, _, _, b'f', b't', b'y', b'p', ..] => Some("m4a"),
+        _ => None,
+    }
+}
+
+/// Sniffs the format from the start of `reader`, which is left rewound to the start.
+pub fn sniff_reader<R: Read + Seek>(mut reader: R) -> std::io::Result<Option<&'static str>> {
+    let mut header = Vec::new();
+    reader.seek(SeekFrom::Start(0))?;
+    (&mut reader).take(SNIFF_LEN).read_to_end(&mut header)?;
+    reader.seek(SeekFrom::Start(0))?;
+
+    Ok(sniff_format(&header))
+}
+
+/// The loaded audio data. Clones share their read position, the same way clones of a
+/// `std::fs::File` share the OS cursor, so the player can tell how far the decoder has read.
+pub enum MediaHandle {
+    File(File),
+    Memory { data: Arc<Vec<u8>>, position: Arc<AtomicU64> },
+}
+
+impl MediaHandle {
+    pub fn from_bytes(data: Vec<u8>) -> Self {
+        Self::Memory {
+            data: Arc::new(data),
+            position: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// A handle to the same data with a cursor of its own, so it can be read without disturbing
+    /// the decoder. Files can only share their cursor, so this is `None` for them.
+    pub fn independent_clone(&self) -> Option<Self> {
+        match self {
+            Self::File(_) => None,
+            Self::Memory { data, .. } => Some(Self::Memory {
+                data: Arc::clone(data),
+                position: Arc::new(AtomicU64::new(0)),
+            }),
+        }
+    }
+
+    pub fn try_clone(&self) -> std::io::Result<Self> {
+        match self {
+            Self::File(file) => Ok(Self::File(file.try_clone()?)),
+            Self::Memory { data, position } => Ok(Self::Memory {
+                data: Arc::clone(data),
+                position: Arc::clone(position),
+            }),
+        }
+    }
+}
+
+impl Read for &MediaHandle {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match *self {
+            MediaHandle::File(file) => {
+                let mut file: &File = file;
+                file.read(buf)
+            }
+            MediaHandle::Memory { data, position } => {
+                let start = (position.load(Ordering::SeqCst) as usize).min(data.len());
+                let bytes_read = (data.len() - start).min(buf.len());
+                buf[..bytes_read].copy_from_slice(&data[start..start + bytes_read]);
+                position.store((start + bytes_read) as u64, Ordering::SeqCst);
+                Ok(bytes_read)
+            }
+        }
+    }
+}
+
+impl Seek for &MediaHandle {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        match *self {
+            MediaHandle::File(file) => {
+                let mut file: &File = file;
+                file.seek(pos)
+            }
+            MediaHandle::Memory { data, position } => {
+                let target = match pos {
+                    SeekFrom::Start(offset) => Some(offset),
+                    SeekFrom::End(offset) => (data.len() as u64).checked_add_signed(offset),
+                    SeekFrom::Current(offset) => position.load(Ordering::SeqCst).checked_add_signed(offset),
+                };
+
+                match target {
+                    Some(target) => {
+                        position.store(target, Ordering::SeqCst);
+                        Ok(target)
+                    }
+                    None => Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        "invalid seek to a negative or overflowing position",
+                    )),
+                }
+            }
+        }
+    }
+}
+
+impl Read for MediaHandle {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        (&*self).read(buf)
+    }
+}
+
+impl Seek for MediaHandle {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        (&*self).seek(pos)
+    }
+}
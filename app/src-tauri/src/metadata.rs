@@ -0,0 +1,73 @@
+use std::{
+    io::{Read, Seek},
+    time::Duration,
+};
+
+use lofty::{
+    file::{AudioFile, TaggedFileExt},
+    picture::PictureType,
+    probe::Probe,
+    tag::Accessor,
+};
+use serde::{Deserialize, Serialize};
+
+/// The tags of a loaded track. Every field is optional, since plenty of files are untagged or
+/// only partly tagged.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TrackMetadata {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub duration: Option<Duration>,
+}
+
+/// An image embedded in a track's tags, usually the album cover.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CoverArt {
+    pub mime: String,
+    pub data: Vec<u8>,
+}
+
+/// Reads the ID3, Vorbis, FLAC or other tags from the start of `reader`, along with the front
+/// cover, or the first embedded picture if none is marked as the cover. Files whose format can't
+/// be recognised just get empty metadata.
+pub fn read_tags<R: Read + Seek>(reader: R) -> (TrackMetadata, Option<CoverArt>) {
+    let tagged_file = match Probe::new(reader).guess_file_type().map(|probe| probe.read()) {
+        Ok(Ok(tagged_file)) => tagged_file,
+        _ => return (TrackMetadata::default(), None),
+    };
+
+    let duration = Some(tagged_file.properties().duration()).filter(|duration| !duration.is_zero());
+    let tag = match tagged_file.primary_tag().or_else(|| tagged_file.first_tag()) {
+        Some(tag) => tag,
+        None => {
+            let metadata = TrackMetadata {
+                duration,
+                ..TrackMetadata::default()
+            };
+            return (metadata, None);
+        }
+    };
+
+    let metadata = TrackMetadata {
+        title: tag.title().map(|title| title.into_owned()),
+        artist: tag.artist().map(|artist| artist.into_owned()),
+        album: tag.album().map(|album| album.into_owned()),
+        duration,
+    };
+
+    let pictures = tag.pictures();
+    let cover_art = pictures
+        .iter()
+        .find(|picture| picture.pic_type() == PictureType::CoverFront)
+        .or_else(|| pictures.first())
+        .map(|picture| CoverArt {
+            mime: match picture.mime_type() {
+                Some(mime) => mime.as_str().to_string(),
+                None => String::from("application/octet-stream"),
+            },
+            data: picture.data().to_vec(),
+        });
+
+    (metadata, cover_art)
+}
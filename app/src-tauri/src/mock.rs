@@ -0,0 +1,128 @@
+use std::time::Duration;
+
+use crate::player::{AudioPlayer, PlaybackState, PlayerError, PlayerResult};
+
+/// A player that only keeps track of what it was told to do, without decoding anything or opening
+/// an output device. It lets `Music` be driven, e.g. through crossfades, batch operations and
+/// eviction, on machines without sound hardware.
+///
+/// Playback doesn't move on by itself, so `elapsed` only changes when seeking or stopping, and
+/// fades finish straight away. A mock put in `PlaybackState::DeviceLost` fails to play, like a
+/// `Player` whose device went away.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MockPlayer {
+    pub state: PlaybackState,
+    pub volume: f32,
+    pub master_volume: f32,
+    pub elapsed: Duration,
+    /// The length of the loaded track, or `None` while nothing is loaded.
+    pub duration: Option<Duration>,
+    /// How many fades were started, in or out.
+    pub fades: usize,
+}
+
+impl Default for MockPlayer {
+    fn default() -> Self {
+        Self {
+            state: PlaybackState::Empty,
+            volume: 1.0,
+            master_volume: 1.0,
+            elapsed: Duration::ZERO,
+            duration: None,
+            fades: 0,
+        }
+    }
+}
+
+impl MockPlayer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A player that is playing a track of the given length from the start.
+    pub fn playing(duration: Duration) -> Self {
+        Self {
+            state: PlaybackState::Playing,
+            duration: Some(duration),
+            ..Self::default()
+        }
+    }
+
+    fn is_loaded(&self) -> bool {
+        self.duration.is_some()
+    }
+}
+
+impl AudioPlayer for MockPlayer {
+    fn play(&mut self) -> PlayerResult<()> {
+        if self.state == PlaybackState::DeviceLost {
+            return Err(PlayerError::DeviceLost);
+        }
+        if self.is_loaded() {
+            self.state = PlaybackState::Playing;
+        }
+        Ok(())
+    }
+
+    fn pause(&mut self) {
+        if self.state == PlaybackState::Playing {
+            self.state = PlaybackState::Paused;
+        }
+    }
+
+    fn stop(&mut self) {
+        if self.is_loaded() {
+            self.state = PlaybackState::Stopped;
+            self.elapsed = Duration::ZERO;
+        }
+    }
+
+    fn seek(&mut self, time_offset: Duration) -> PlayerResult<()> {
+        let duration = self.duration.ok_or(PlayerError::NoFileHandle)?;
+        self.elapsed = time_offset.min(duration);
+        if self.state == PlaybackState::Stopped {
+            self.state = PlaybackState::Playing;
+        }
+
+        Ok(())
+    }
+
+    fn volume(&self) -> f32 {
+        self.volume
+    }
+
+    fn set_volume(&mut self, volume: f32) {
+        self.volume = volume;
+    }
+
+    fn set_master_volume(&mut self, volume: f32) {
+        self.master_volume = volume;
+    }
+
+    fn fade_in(&mut self, _duration: Duration) -> PlayerResult<()> {
+        self.fades += 1;
+        self.play()
+    }
+
+    fn fade_out(&mut self, _duration: Duration, pause: bool) {
+        self.fades += 1;
+        if pause {
+            self.pause();
+        }
+    }
+
+    fn elapsed(&self) -> PlayerResult<Duration> {
+        match self.is_loaded() {
+            true => Ok(self.elapsed),
+            false => Err(PlayerError::NoFileHandle),
+        }
+    }
+
+    fn duration(&self) -> Option<Duration> {
+        self.duration
+    }
+
+    fn state(&self) -> PlaybackState {
+        self.state.clone()
+    }
+}
@@ -0,0 +1,253 @@
+use std::{
+    io::{self, BufReader, Read, Seek, SeekFrom},
+    time::Duration,
+};
+
+/// How far apart `SparseIndex` keeps its checkpoints.
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How many bytes past a broken frame header to look for the next frame before giving up.
+const MAX_RESYNC: u64 = 4096;
+
+/// Bitrates in kbps by bitrate index, for MPEG-1 layers I, II and III and then for MPEG-2 and 2.5
+/// layer I and layers II and III. Index 0 is the free format, which can't be scanned, and 15 is
+/// invalid.
+const BITRATES: [[u32; 14]; 5] = [
+    [32, 64, 96, 128, 160, 192, 224, 256, 288, 320, 352, 384, 416, 448],
+    [32, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384],
+    [32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320],
+    [32, 48, 56, 64, 80, 96, 112, 128, 144, 160, 176, 192, 224, 256],
+    [8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160],
+];
+
+/// The part of an MP3 frame header needed to step from one frame to the next.
+pub struct FrameHeader {
+    /// The length of the whole frame in bytes, header included.
+    pub len: u64,
+    /// How many samples per channel the frame decodes to.
+    pub samples: u64,
+    pub sample_rate: u32,
+}
+
+impl FrameHeader {
+    pub fn parse(header: [u8; 4]) -> Option<Self> {
+        if header[0] != 0xFF || header[1] & 0xE0 != 0xE0 {
+            return None;
+        }
+
+        // 0 is MPEG-2.5, 2 is MPEG-2 and 3 is MPEG-1. Layers are numbered backwards, 1 is layer III.
+        let version = (header[1] >> 3) & 0b11;
+        let layer = (header[1] >> 1) & 0b11;
+        let bitrate_index = usize::from(header[2] >> 4);
+        let sample_rate_index = usize::from((header[2] >> 2) & 0b11);
+        let padding = u64::from((header[2] >> 1) & 1);
+        if version == 1 || layer == 0 || bitrate_index == 0 || bitrate_index == 15 || sample_rate_index == 3 {
+            return None;
+        }
+
+        let mpeg1 = version == 3;
+        let bitrates = match (mpeg1, layer) {
+            (true, 3) => &BITRATES[0],
+            (true, 2) => &BITRATES[1],
+            (true, _) => &BITRATES[2],
+            (false, 3) => &BITRATES[3],
+            (false, _) => &BITRATES[4],
+        };
+        let bitrate = u64::from(bitrates[bitrate_index - 1]) * 1000;
+        let sample_rate = [44100, 48000, 32000][sample_rate_index]
+            >> match version {
+                3 => 0,
+                2 => 1,
+                _ => 2,
+            };
+
+        let samples = match (layer, mpeg1) {
+            (3, _) => 384,
+            (2, _) | (1, true) => 1152,
+            _ => 576,
+        };
+        let len = match layer {
+            3 => (12 * bitrate / u64::from(sample_rate) + padding) * 4,
+            _ => samples / 8 * bitrate / u64::from(sample_rate) + padding,
+        };
+
+        Some(Self {
+            len,
+            samples,
+            sample_rate,
+        })
+    }
+
+    fn duration(&self) -> Duration {
+        Duration::from_nanos(self.samples * 1_000_000_000 / u64::from(self.sample_rate))
+    }
+}
+
+/// Where the audio frames start, past the ID3v2 tag if the file begins with one.
+fn first_frame_offset<R: Read + Seek>(reader: &mut R) -> io::Result<u64> {
+    let mut header = [0u8; 10];
+    reader.seek(SeekFrom::Start(0))?;
+    if reader.read_exact(&mut header).is_err() || &header[..3] != b"ID3" {
+        return Ok(0);
+    }
+
+    // The size is stored in 7 bits per byte, and doesn't count the header or the optional footer.
+    let size = header[6..10]
+        .iter()
+        .fold(0u64, |size, byte| (size << 7) | u64::from(byte & 0x7F));
+    let footer = match header[5] & 0x10 {
+        0 => 0,
+        _ => 10,
+    };
+
+    Ok(10 + size + footer)
+}
+
+/// Looks for the next frame header in the bytes after `position`, for when the frame at
+/// `position` is broken or followed by junk. Leaves `reader` just past the header it found.
+fn resync<R: Read + Seek>(reader: &mut BufReader<R>, position: u64) -> io::Result<Option<(u64, FrameHeader)>> {
+    let mut window = Vec::new();
+    reader.seek(SeekFrom::Start(position + 1))?;
+    reader.by_ref().take(MAX_RESYNC + 3).read_to_end(&mut window)?;
+
+    let found = window.windows(4).enumerate().find_map(|(skipped, bytes)| {
+        FrameHeader::parse([bytes[0], bytes[1], bytes[2], bytes[3]]).map(|header| (position + 1 + skipped as u64, header))
+    });
+    if let Some((offset, _)) = &found {
+        reader.seek(SeekFrom::Start(offset + 4))?;
+    }
+
+    Ok(found)
+}
+
+/// Estimates how long the file is from the bitrate of its first frame and the number of bytes from
+/// there to the end, which is exact for constant bitrate files and a rough guess otherwise.
+pub fn estimate_duration<R: Read + Seek>(reader: R) -> io::Result<Option<Duration>> {
+    let mut reader = BufReader::new(reader);
+    let len = reader.seek(SeekFrom::End(0))?;
+    let mut position = first_frame_offset(&mut reader)?;
+    reader.seek(SeekFrom::Start(position))?;
+
+    let mut bytes = [0u8; 4];
+    if reader.read_exact(&mut bytes).is_err() {
+        return Ok(None);
+    }
+    let header = match FrameHeader::parse(bytes) {
+        Some(header) => header,
+        None => match resync(&mut reader, position)? {
+            Some((offset, header)) => {
+                position = offset;
+                header
+            }
+            None => return Ok(None),
+        },
+    };
+
+    let nanos = u128::from(len.saturating_sub(position)) * u128::from(header.samples) * 1_000_000_000
+        / (u128::from(header.len.max(1)) * u128::from(header.sample_rate));
+    Ok(Some(Duration::from_nanos(u64::try_from(nanos).unwrap_or(u64::MAX))))
+}
+
+/// Steps through every frame header and returns the offset of the frame that runs past the end of
+/// the file, if the file was cut off partway through one. The file ending cleanly after a frame,
+/// or in something that isn't a frame like an ID3v1 tag, gives `None`.
+pub fn truncated_at<R: Read + Seek>(reader: R) -> io::Result<Option<u64>> {
+    let mut reader = BufReader::new(reader);
+    let len = reader.seek(SeekFrom::End(0))?;
+    let mut position = first_frame_offset(&mut reader)?;
+    reader.seek(SeekFrom::Start(position))?;
+
+    loop {
+        let mut bytes = [0u8; 4];
+        if reader.read_exact(&mut bytes).is_err() {
+            return Ok(None);
+        }
+
+        let header = match FrameHeader::parse(bytes) {
+            Some(header) => header,
+            None => match resync(&mut reader, position)? {
+                Some((offset, header)) => {
+                    position = offset;
+                    header
+                }
+                None => return Ok(None),
+            },
+        };
+
+        if position + header.len > len {
+            return Ok(Some(position));
+        }
+        reader.seek_relative(header.len as i64 - 4)?;
+        position += header.len;
+    }
+}
+
+/// The frames of an MP3 that is too large to index up front, found as seeks need them. It keeps
+/// a checkpoint every 10 seconds of the audio it has scanned through, so a seek only has to read
+/// the frame headers from the closest checkpoint before it, and seeking further into the file
+/// than before is as slow as reading the headers of everything in between once.
+#[derive(Clone, Default)]
+pub struct SparseIndex {
+    /// The times and byte offsets of frames, sorted and no more than one interval apart.
+    checkpoints: Vec<(Duration, u64)>,
+}
+
+impl SparseIndex {
+    /// Finds the frame that is playing at `target` and returns its byte offset along with the
+    /// time it starts at. A target past the end gives the last frame, and `None` means there
+    /// were no frames to be found.
+    pub fn find_frame<R: Read + Seek>(&mut self, reader: R, target: Duration) -> io::Result<Option<(u64, Duration)>> {
+        let mut reader = BufReader::new(reader);
+        if self.checkpoints.is_empty() {
+            let offset = first_frame_offset(&mut reader)?;
+            self.checkpoints.push((Duration::ZERO, offset));
+        }
+
+        let checkpoint = self
+            .checkpoints
+            .partition_point(|(time, _)| *time <= target)
+            .saturating_sub(1);
+        let (mut time, mut position) = self.checkpoints[checkpoint];
+        reader.seek(SeekFrom::Start(position))?;
+
+        let mut found = None;
+        loop {
+            let mut bytes = [0u8; 4];
+            if reader.read_exact(&mut bytes).is_err() {
+                break;
+            }
+
+            let header = match FrameHeader::parse(bytes) {
+                Some(header) => header,
+                None => match resync(&mut reader, position)? {
+                    Some((offset, header)) => {
+                        position = offset;
+                        header
+                    }
+                    None => break,
+                },
+            };
+
+            found = Some((position, time));
+            if self
+                .checkpoints
+                .last()
+                .is_some_and(|(last, _)| time >= last.saturating_add(CHECKPOINT_INTERVAL))
+            {
+                self.checkpoints.push((time, position));
+            }
+
+            let end = time.saturating_add(header.duration());
+            if end > target {
+                break;
+            }
+
+            // Stepping relative to the current position keeps the buffered bytes.
+            reader.seek_relative(header.len as i64 - 4)?;
+            position += header.len;
+            time = end;
+        }
+
+        Ok(found)
+    }
+}
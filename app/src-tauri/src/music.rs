@@ -1,40 +1,1102 @@
-use std::sync::{Arc, RwLock};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockError, Weak};
+use std::task::Poll;
+use std::time::{Duration, Instant};
 
-use dashmap::DashMap;
+use dashmap::mapref::entry::Entry;
+use dashmap::{DashMap, DashSet};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Semaphore;
 
-use crate::player::Player;
+use crate::handle::{self, PlayerHandle};
+use crate::player::{AudioPlayer, PlaybackState, Player, PlayerConfig, PlayerError, PlayerResult, TrackLoader};
+use crate::trace;
 
 type PlayerId = String;
 
-/// Manages all the current players in the app.
-pub struct Music {
-    pub players: Arc<DashMap<PlayerId, Arc<RwLock<Player>>>>,
+/// How many files `Music::load_many()` loads at the same time.
+const LOAD_MANY_CONCURRENCY: usize = 4;
+
+/// One of the loads `Music::load_many()` runs, which finishes with the player's id and result.
+type Load<'a> = Pin<Box<dyn Future<Output = (PlayerId, PlayerResult<()>)> + Send + 'a>>;
+
+/// Locks a player for reading. A player whose lock was poisoned by a panic is still usable, so
+/// the poison is cleared with a warning instead of locking the player out for good.
+fn read_player<'a, P>(id: &str, player: &'a RwLock<P>) -> RwLockReadGuard<'a, P> {
+    player.read().unwrap_or_else(|poisoned| {
+        warn_poisoned(id, player);
+        poisoned.into_inner()
+    })
 }
 
-impl Music {
+/// Locks a player for reading like `read_player()`, but returns `None` straight away if someone
+/// is holding it for writing.
+fn try_read_player<'a, P>(id: &str, player: &'a RwLock<P>) -> Option<RwLockReadGuard<'a, P>> {
+    match player.try_read() {
+        Ok(player) => Some(player),
+        Err(TryLockError::Poisoned(poisoned)) => {
+            warn_poisoned(id, player);
+            Some(poisoned.into_inner())
+        }
+        Err(TryLockError::WouldBlock) => None,
+    }
+}
+
+/// Locks a player for writing, recovering from poisoning the same way as `read_player()`.
+fn write_player<'a, P>(id: &str, player: &'a RwLock<P>) -> RwLockWriteGuard<'a, P> {
+    player.write().unwrap_or_else(|poisoned| {
+        warn_poisoned(id, player);
+        poisoned.into_inner()
+    })
+}
+
+fn warn_poisoned<P>(id: &str, player: &RwLock<P>) {
+    trace::warning!("a player was poisoned by a panic while it was locked, recovering it", id = id);
+    player.clear_poison();
+}
+
+/// The players `Music::advance_queue()` plays one after the other.
+#[derive(Default)]
+struct PlayerQueue {
+    ids: Vec<PlayerId>,
+    /// Where in `ids` the player that is playing is, `None` before the queue has started.
+    position: Option<usize>,
+}
+
+/// What gets saved for a single player so it can be recreated after a restart.
+#[derive(Serialize, Deserialize)]
+pub struct PlayerState {
+    pub id: PlayerId,
+    pub path: Option<String>,
+    pub volume: f32,
+    pub elapsed: Option<Duration>,
+}
+
+/// The saved state of every player, as written by `Music::save_state`.
+#[derive(Serialize, Deserialize)]
+pub struct MusicState {
+    pub players: Vec<PlayerState>,
+}
+
+/// What a player is doing at one moment, for rendering an overview of every player at once.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlayerSnapshot {
+    pub id: PlayerId,
+    pub state: PlaybackState,
+    pub elapsed: Option<Duration>,
+    pub duration: Option<Duration>,
+    pub volume: f32,
+}
+
+/// Manages all the current players in the app. Each player runs on a thread of its own behind a
+/// `PlayerHandle`, so a slow seek or load on one player never holds up a caller waiting on a lock,
+/// unless another `AudioPlayer` is picked, like `MockPlayer` to run without sound hardware.
+/// Creating, moving, saving and loading players needs the real thing.
+pub struct Music<P = PlayerHandle> {
+    pub players: Arc<DashMap<PlayerId, Arc<RwLock<P>>>>,
+    master_volume: Mutex<f32>,
+    /// The most players kept at once, or `None` for no limit.
+    capacity: Option<usize>,
+    /// When each player was last added or looked up, for picking which one to evict.
+    last_access: DashMap<PlayerId, Instant>,
+    /// The group each tagged player belongs to, e.g. "sfx" or "music".
+    tags: DashMap<PlayerId, String>,
+    /// The volume of each group of tagged players. Groups that were never set are at full volume.
+    group_volumes: DashMap<String, f32>,
+    /// Whether starting a player with `play()` pauses every other one.
+    exclusive_playback: AtomicBool,
+    /// The groups that exclusive playback leaves alone, e.g. "sfx".
+    exclusive_exempt: DashSet<String>,
+    queue: Arc<Mutex<PlayerQueue>>,
+}
+
+impl<P: AudioPlayer> Default for Music<P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<P: AudioPlayer> Music<P> {
     pub fn new() -> Self {
         Self {
             players: Arc::new(DashMap::new()),
+            master_volume: Mutex::new(1.0),
+            capacity: None,
+            last_access: DashMap::new(),
+            tags: DashMap::new(),
+            group_volumes: DashMap::new(),
+            exclusive_playback: AtomicBool::new(false),
+            exclusive_exempt: DashSet::new(),
+            queue: Arc::default(),
+        }
+    }
+
+    /// Keeps at most `max_players` players around. Adding one more stops and removes the player
+    /// that was least recently added or looked up, so a long running app doesn't pile up idle
+    /// players with their files and seek indexes still loaded.
+    pub fn with_capacity(max_players: usize) -> Self {
+        Self {
+            capacity: Some(max_players),
+            ..Self::new()
+        }
+    }
+
+    /// Lets only one player play at a time, for a single-stream music player. Starting a player
+    /// with `play()` pauses whichever ones were playing before. See `set_exclusive()` for how
+    /// this works with tags.
+    pub fn with_exclusive_playback() -> Self {
+        Self {
+            exclusive_playback: AtomicBool::new(true),
+            ..Self::new()
         }
     }
 
     /// Adds a player to the app memory
-    pub fn add_player(&self, key: String, player: Player) {
-        self.players.insert(key, Arc::new(RwLock::new(player)));
+    pub fn add_player(&self, key: String, mut player: P) {
+        player.set_master_volume(self.mix_volume(&key));
+        self.players.insert(key.clone(), Arc::new(RwLock::new(player)));
+        self.touch(&key);
+        self.evict_over_capacity(&key);
+    }
+
+    /// Stores `spare` under `key` unless another player got there first, and returns the player
+    /// that ends up stored. The lookup and insert happen under the same entry lock.
+    fn get_or_insert(&self, key: &str, mut spare: P) -> Arc<RwLock<P>> {
+        spare.set_master_volume(self.mix_volume(key));
+        let player = match self.players.entry(key.to_string()) {
+            Entry::Occupied(entry) => Arc::clone(entry.get()),
+            Entry::Vacant(entry) => {
+                let player = Arc::new(RwLock::new(spare));
+                entry.insert(Arc::clone(&player));
+                self.touch(key);
+                // Inserting released the shard lock, which evicting may need again.
+                self.evict_over_capacity(key);
+                return player;
+            }
+        };
+
+        self.touch(key);
+        player
     }
 
     pub fn remove_player(&self, key: &str) {
         self.players.remove(key);
+        self.forget(key);
+    }
+
+    /// Drops what is stored about a player next to the map, once the player itself is gone.
+    fn forget(&self, key: &str) {
+        self.last_access.remove(key);
+        self.tags.remove(key);
+    }
+
+    fn touch(&self, key: &str) {
+        self.last_access.insert(key.to_string(), Instant::now());
+    }
+
+    /// Stops and removes the least recently used players until there are no more than the
+    /// capacity, never evicting `keep`. Players put straight into `players` have no access time
+    /// and are evicted first.
+    fn evict_over_capacity(&self, keep: &str) {
+        let capacity = match self.capacity {
+            Some(capacity) => capacity,
+            None => return,
+        };
+
+        while self.players.len() > capacity {
+            let oldest = self
+                .players
+                .iter()
+                .filter(|entry| entry.key() != keep)
+                .min_by_key(|entry| self.last_access.get(entry.key()).map(|access| *access))
+                .map(|entry| entry.key().clone());
+            let oldest = match oldest {
+                Some(oldest) => oldest,
+                None => return,
+            };
+
+            self.forget(&oldest);
+            if let Some((id, player)) = self.players.remove(&oldest) {
+                write_player(&id, &player).stop();
+            }
+        }
     }
 
-    pub fn get_player(&self, key: &str) -> Option<Arc<RwLock<Player>>> {
-        self.players.get(key).map(|entry| Arc::clone(entry.value()))
+    /// Stops every player before removing it. Dropping the map entry alone isn't enough, since audio
+    /// keeps playing for as long as anyone else still holds a reference to the player.
+    ///
+    /// The players are taken out of the map first and only then stopped and dropped, one at a time,
+    /// since dropping a player waits for its output to fade out. Players added while this runs are
+    /// kept.
+    pub fn clear_all(&self) {
+        let removed: Vec<(PlayerId, Arc<RwLock<P>>)> = self
+            .list_players()
+            .into_iter()
+            .filter_map(|(id, _)| self.players.remove(&id))
+            .collect();
+
+        for (id, player) in removed {
+            self.forget(&id);
+            write_player(&id, &player).stop();
+        }
     }
 
-    pub fn list_players(&self) -> Vec<(String, Arc<RwLock<Player>>)> {
+    /// Stops and removes every player that has played through to the end, and returns their keys,
+    /// e.g. to clean up after one-shot sound effects. Each player is checked once before its map
+    /// entry is locked and again, without waiting on the player, while it is removed. So one
+    /// that gets something new loaded meanwhile is kept, and so is one that someone has locked
+    /// at that moment, which the next call picks up.
+    pub fn drain_finished(&self) -> Vec<String> {
+        let mut drained = Vec::new();
+        for (id, player) in self.list_players() {
+            // Waiting on a player's lock while holding the map's shard could deadlock against a
+            // thread that holds the player and touches the map, like a finished callback
+            // advancing the queue, so the state is checked before taking the shard.
+            if read_player(&id, &player).state() != PlaybackState::Finished {
+                continue;
+            }
+
+            // It's checked again in case the player started over meanwhile, but without waiting:
+            // a player someone is busy with is left for the next drain.
+            let removed = self.players.remove_if(&id, |id, player| {
+                try_read_player(id, player).is_some_and(|player| player.state() == PlaybackState::Finished)
+            });
+            if let Some((id, player)) = removed {
+                write_player(&id, &player).stop();
+                self.forget(&id);
+                drained.push(id);
+            }
+        }
+
+        drained
+    }
+
+    /// Moves the player under `old_key` to `new_key` without touching the player itself, so
+    /// whatever it's playing carries on. There's a moment where the player is under neither key,
+    /// but never under both, and it's put back under `old_key` if `new_key` gets taken meanwhile.
+    pub fn rename(&self, old_key: &str, new_key: &str) -> PlayerResult<()> {
+        if old_key == new_key {
+            return match self.contains(old_key) {
+                true => Ok(()),
+                false => Err(PlayerError::PlayerNotFound),
+            };
+        }
+
+        if self.contains(new_key) {
+            return Err(PlayerError::PlayerAlreadyExists);
+        }
+
+        let (_, player) = self.players.remove(old_key).ok_or(PlayerError::PlayerNotFound)?;
+        match self.players.entry(new_key.to_string()) {
+            Entry::Vacant(entry) => {
+                entry.insert(player);
+                if let Some((_, access)) = self.last_access.remove(old_key) {
+                    self.last_access.insert(new_key.to_string(), access);
+                }
+                if let Some((_, tag)) = self.tags.remove(old_key) {
+                    self.tags.insert(new_key.to_string(), tag);
+                }
+                Ok(())
+            }
+            Entry::Occupied(entry) => {
+                drop(entry);
+                self.players.insert(old_key.to_string(), player);
+                Err(PlayerError::PlayerAlreadyExists)
+            }
+        }
+    }
+
+    pub fn get_player(&self, key: &str) -> Option<Arc<RwLock<P>>> {
+        let player = self.players.get(key).map(|entry| Arc::clone(entry.value()))?;
+        self.touch(key);
+        Some(player)
+    }
+
+    /// Runs `f` with the player under `key` locked for writing, and releases the lock as soon as
+    /// it returns. Returns `None` if there is no such player.
+    pub fn with_player_mut<R>(&self, key: &str, f: impl FnOnce(&mut P) -> R) -> Option<R> {
+        let player = self.get_player(key)?;
+        let mut player = write_player(key, &player);
+        Some(f(&mut player))
+    }
+
+    /// Like `with_player_mut()`, but gives up and returns `None` straight away if someone else is
+    /// holding the player's lock, so a render loop never has to wait on it.
+    pub fn try_with_player_mut<R>(&self, key: &str, f: impl FnOnce(&mut P) -> R) -> Option<R> {
+        let player = self.get_player(key)?;
+        let mut player = match player.try_write() {
+            Ok(player) => player,
+            Err(TryLockError::Poisoned(poisoned)) => {
+                warn_poisoned(key, &player);
+                poisoned.into_inner()
+            }
+            Err(TryLockError::WouldBlock) => return None,
+        };
+        Some(f(&mut player))
+    }
+
+    /// Puts the player under `key` in the group `tag`, replacing any tag it had. The tag goes away
+    /// along with the player.
+    pub fn set_tag(&self, key: &str, tag: &str) -> PlayerResult<()> {
+        if !self.contains(key) {
+            return Err(PlayerError::PlayerNotFound);
+        }
+
+        self.tags.insert(key.to_string(), tag.to_string());
+        self.apply_mix_volume(key);
+        Ok(())
+    }
+
+    pub fn get_tag(&self, key: &str) -> Option<String> {
+        self.tags.get(key).map(|tag| tag.value().clone())
+    }
+
+    pub fn remove_tag(&self, key: &str) {
+        self.tags.remove(key);
+        self.apply_mix_volume(key);
+    }
+
+    /// The keys of every player tagged with `tag`, e.g. to pause all the "sfx" players.
+    pub fn players_with_tag(&self, tag: &str) -> Vec<String> {
+        self.tags
+            .iter()
+            .filter(|entry| entry.value() == tag)
+            .map(|entry| entry.key().clone())
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.players.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.players.is_empty()
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.players.contains_key(key)
+    }
+
+    pub fn list_players(&self) -> Vec<(String, Arc<RwLock<P>>)> {
         self.players
             .iter()
             .map(|entry| (entry.key().clone(), Arc::clone(entry.value())))
             .collect()
     }
+
+    /// The keys of every player that is playing right now, e.g. for a "now playing" view. Each
+    /// player is only read locked while its state is checked.
+    pub fn find_playing(&self) -> Vec<String> {
+        self.list_players()
+            .into_iter()
+            .filter(|(id, player)| read_player(id, player).state() == PlaybackState::Playing)
+            .map(|(id, _)| id)
+            .collect()
+    }
+
+    /// Runs `f` on every player along with its key, e.g. to pause or mute them all at once.
+    ///
+    /// The players are listed first, so no map lock is held while `f` runs, and each one is then
+    /// write locked only for its own call. No two players are ever locked at the same time, the
+    /// same as in `crossfade_to()`, so the two can't deadlock against each other. `f` mustn't go
+    /// through this `Music` to reach the player it was handed, since that one is still locked.
+    pub fn for_each_mut(&self, f: impl Fn(&str, &mut P)) {
+        for (id, player) in self.list_players() {
+            f(&id, &mut write_player(&id, &player));
+        }
+    }
+
+    /// Turns exclusive playback on or off, see `with_exclusive_playback()`. Turning it on leaves
+    /// the players that are already playing alone until the next `play()`.
+    ///
+    /// Exclusivity goes by tags: players in a group exempted with `set_exclusive_exempt()` neither
+    /// pause the others when they start nor get paused when another one does, so sound effects can
+    /// play over the music. Untagged players are never exempt.
+    pub fn set_exclusive(&self, enabled: bool) {
+        self.exclusive_playback.store(enabled, Ordering::SeqCst);
+    }
+
+    pub fn is_exclusive(&self) -> bool {
+        self.exclusive_playback.load(Ordering::SeqCst)
+    }
+
+    /// Exempts the players tagged with `tag` from exclusive playback, or stops exempting them.
+    /// Players tagged later are exempt too.
+    pub fn set_exclusive_exempt(&self, tag: &str, exempt: bool) {
+        if exempt {
+            self.exclusive_exempt.insert(tag.to_string());
+        } else {
+            self.exclusive_exempt.remove(tag);
+        }
+    }
+
+    fn is_exclusive_exempt(&self, key: &str) -> bool {
+        self.get_tag(key).is_some_and(|tag| self.exclusive_exempt.contains(&tag))
+    }
+
+    /// Starts the player under `key`. With exclusive playback, every other player that is playing
+    /// gets paused first, one lock at a time like `for_each_player()`, unless one of the two is in
+    /// an exempt group.
+    pub fn play(&self, key: &str) -> PlayerResult<()> {
+        let player = self.get_player(key).ok_or(PlayerError::PlayerNotFound)?;
+        if self.is_exclusive() && !self.is_exclusive_exempt(key) {
+            for (id, other) in self.list_players() {
+                if id != key && !self.is_exclusive_exempt(&id) {
+                    let mut other = write_player(&id, &other);
+                    if other.state() == PlaybackState::Playing {
+                        other.pause();
+                    }
+                }
+            }
+        }
+
+        let mut player = write_player(key, &player);
+        player.play()
+    }
+
+    pub fn pause_all(&self) {
+        self.for_each_player(P::pause);
+    }
+
+    /// Starts every player, skipping the ones that can't be, like those whose output device was
+    /// lost.
+    pub fn play_all(&self) {
+        self.for_each_player(|player| {
+            let _ = player.play();
+        });
+    }
+
+    /// Stops every player but keeps them around, unlike `clear_all()`.
+    pub fn stop_all(&self) {
+        self.for_each_player(P::stop);
+    }
+
+    /// Runs `f` on every player in turn. The map is snapshotted first and each write lock is
+    /// released before the next one is taken, so this never waits on a lock while holding another
+    /// and can't deadlock against `crossfade_to()`, which does the same. A caller must not hold a
+    /// player's lock while calling this, though.
+    fn for_each_player(&self, f: impl Fn(&mut P)) {
+        for (id, player) in self.list_players() {
+            f(&mut write_player(&id, &player));
+        }
+    }
+
+    pub fn master_volume(&self) -> f32 {
+        *self.master_volume.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Sets a level that every player's own volume is multiplied by, including players added
+    /// later. Fades that are still running carry on, ramping within the new level.
+    pub fn set_master_volume(&self, volume: f32) {
+        *self.master_volume.lock().unwrap_or_else(PoisonError::into_inner) = volume;
+        for (id, _) in self.list_players() {
+            self.apply_mix_volume(&id);
+        }
+    }
+
+    /// The volume of the players tagged with `tag`, 1.0 unless set with `set_group_volume()`.
+    pub fn group_volume(&self, tag: &str) -> f32 {
+        self.group_volumes.get(tag).map_or(1.0, |volume| *volume)
+    }
+
+    /// Sets a level that the volume of every player tagged with `tag` is multiplied by, including
+    /// players tagged later. A player plays at its own volume times its group's volume times the
+    /// master volume, where the product goes through the player's volume curve as a whole.
+    pub fn set_group_volume(&self, tag: &str, volume: f32) {
+        self.group_volumes.insert(tag.to_string(), volume);
+        for id in self.players_with_tag(tag) {
+            self.apply_mix_volume(&id);
+        }
+    }
+
+    /// The level the player under `key` is mixed at, its group's volume times the master volume.
+    fn mix_volume(&self, key: &str) -> f32 {
+        let group_volume = self.get_tag(key).map_or(1.0, |tag| self.group_volume(&tag));
+        group_volume * self.master_volume()
+    }
+
+    /// Hands the mix level to the player under `key`. Like `for_each_player()`, this must not be
+    /// called while holding a player's lock.
+    fn apply_mix_volume(&self, key: &str) {
+        let volume = self.mix_volume(key);
+        if let Some(player) = self.players.get(key).map(|entry| Arc::clone(entry.value())) {
+            write_player(key, &player).set_master_volume(volume);
+        }
+    }
+
+    /// Fades `from` out while fading `to` in over `duration`. Fades run in the background, so each
+    /// player's write lock is only held long enough to start its fade and both ramps overlap.
+    /// Only one of the two locks is held at any time.
+    ///
+    /// `to` is faded in first, so if it can't start playing its error is returned and `from`
+    /// carries on as it was. Crossfading a player into itself does nothing.
+    pub fn crossfade_to(&self, from: &str, to: &str, duration: Duration) -> PlayerResult<()> {
+        let from_player = self.get_player(from).ok_or(PlayerError::PlayerNotFound)?;
+        let to_player = self.get_player(to).ok_or(PlayerError::PlayerNotFound)?;
+        if from == to {
+            return Ok(());
+        }
+
+        write_player(to, &to_player).fade_in(duration)?;
+        write_player(from, &from_player).fade_out(duration, true);
+
+        Ok(())
+    }
+
+    /// Captures the state of every player in one go. Each player is only read locked while its
+    /// own snapshot is taken.
+    pub fn snapshot(&self) -> Vec<PlayerSnapshot> {
+        let mut snapshots = Vec::new();
+        for (id, player) in self.list_players() {
+            let player = read_player(&id, &player);
+
+            snapshots.push(PlayerSnapshot {
+                id,
+                state: player.state(),
+                elapsed: player.elapsed().ok(),
+                duration: player.duration(),
+                volume: player.volume(),
+            });
+        }
+
+        snapshots
+    }
+}
+
+impl Music {
+    /// Returns the player stored under `key`, creating it first if it doesn't exist yet. Opening
+    /// the output device for a new player is slow, so that happens before the map entry is locked,
+    /// and the lookup and insert then happen under the same entry lock. Concurrent callers always
+    /// end up sharing one player, and the ones that lose the race drop their spare player once the
+    /// lock is released.
+    pub fn get_or_create(&self, key: &str) -> PlayerResult<Arc<RwLock<PlayerHandle>>> {
+        if let Some(player) = self.get_player(key) {
+            return Ok(player);
+        }
+
+        Ok(self.get_or_insert(key, PlayerHandle::spawn(Player::new()?)))
+    }
+
+    /// A clone of the handle stored under `key`, so the player can be driven without holding its
+    /// lock while it works.
+    fn handle(players: &DashMap<PlayerId, Arc<RwLock<PlayerHandle>>>, key: &str) -> Option<PlayerHandle> {
+        let player = players.get(key).map(|entry| Arc::clone(entry.value()))?;
+        let handle = read_player(key, &player).clone();
+        Some(handle)
+    }
+
+    /// Plays the players under `queue` one after the other, like the tracks of an album, without
+    /// merging them into one sink. Nothing starts until `advance_queue()` is called, and each
+    /// player then starts the next one once it finishes. Replaces any queue that was set before.
+    ///
+    /// A player gets its finished callback replaced when its turn comes, see `advance_queue()`.
+    pub fn set_queue(&self, queue: Vec<String>) {
+        *self.queue.lock().unwrap_or_else(PoisonError::into_inner) = PlayerQueue {
+            ids: queue,
+            position: None,
+        };
+    }
+
+    /// Pauses the player whose turn it is and starts the next one in the queue, from the start if
+    /// it had finished already, and returns its key. Keys without a player are skipped, and
+    /// `None` means the queue has run out.
+    ///
+    /// The player that starts gets a finished callback that advances the queue again, replacing
+    /// any callback it had. The callback hands off to another thread, since finished callbacks
+    /// run on the audio thread.
+    pub fn advance_queue(&self) -> Option<String> {
+        Self::advance(&self.players, &self.queue, None)
+    }
+
+    /// Moves the queue on to the next player. `finished` is the key of the player that just
+    /// finished, when a finished callback is advancing it, which only does so if that player's
+    /// turn wasn't already over. No player is locked while it is told what to do.
+    fn advance(
+        players: &Arc<DashMap<PlayerId, Arc<RwLock<PlayerHandle>>>>,
+        queue: &Arc<Mutex<PlayerQueue>>,
+        finished: Option<&str>,
+    ) -> Option<String> {
+        let (previous, next) = {
+            let mut queue = queue.lock().unwrap_or_else(PoisonError::into_inner);
+            let current = queue.position.and_then(|position| queue.ids.get(position)).cloned();
+            if finished.is_some() && current.as_deref() != finished {
+                return None;
+            }
+
+            let start = queue.position.map_or(0, |position| position + 1);
+            let next = (start..queue.ids.len()).find(|index| players.contains_key(&queue.ids[*index]));
+            queue.position = Some(next.unwrap_or(queue.ids.len()));
+            (current, next.map(|index| queue.ids[index].clone()))
+        };
+
+        if let Some(previous) = previous.and_then(|previous| Self::handle(players, &previous)) {
+            let _ = handle::block_on(previous.pause());
+        }
+
+        let next = next?;
+        let player = Self::handle(players, &next)?;
+
+        let weak_players = Arc::downgrade(players);
+        let weak_queue = Arc::downgrade(queue);
+        let id = next.clone();
+        let _ = handle::block_on(player.on_finished(Box::new(move || {
+            let (players, queue, id) = (Weak::clone(&weak_players), Weak::clone(&weak_queue), id.clone());
+            std::thread::spawn(move || {
+                // A queue that outlived its `Music` has nothing left to play.
+                if let (Some(players), Some(queue)) = (players.upgrade(), queue.upgrade()) {
+                    Self::advance(&players, &queue, Some(&id));
+                }
+            });
+        })));
+
+        if handle::block_on(player.state()) == Ok(PlaybackState::Finished) {
+            let _ = handle::block_on(player.restart());
+        }
+        if let Err(error) = handle::block_on(player.play()) {
+            trace::warning!("the next player in the queue couldn't start", id = next, error = error);
+        }
+
+        Some(next)
+    }
+
+    /// Moves the current track of the player under `from` over to the one under `to`, which picks
+    /// it up where it left off, e.g. to switch output devices mid-track. See
+    /// `Player::take_track_from()` for what carries over.
+    ///
+    /// The players are on threads of their own, so the track is taken out of one and then handed
+    /// to the other, and neither is locked meanwhile. A `to` whose device is lost would drop the
+    /// track, so the move fails before `from` gives it up.
+    pub fn move_source(&self, from: &str, to: &str) -> PlayerResult<()> {
+        let from_player = self.get_player(from).ok_or(PlayerError::PlayerNotFound)?;
+        let to_player = self.get_player(to).ok_or(PlayerError::PlayerNotFound)?;
+        if Arc::ptr_eq(&from_player, &to_player) {
+            return Ok(());
+        }
+
+        let from_player = read_player(from, &from_player).clone();
+        let to_player = read_player(to, &to_player).clone();
+        if handle::block_on(to_player.state())? == PlaybackState::DeviceLost {
+            return Err(PlayerError::DeviceLost);
+        }
+
+        let handed_over = handle::block_on(from_player.hand_over_track())?;
+        handle::block_on(to_player.take_over_track(handed_over))
+    }
+
+    /// Writes the path, volume and position of every player to `path` as JSON.
+    pub async fn save_state(&self, path: &str) -> PlayerResult<()> {
+        let mut players = Vec::new();
+        for (id, player) in self.list_players() {
+            let player = read_player(&id, &player).clone();
+            // A player whose thread has stopped has nothing left to save.
+            let (Ok(path), Ok(volume)) = (player.path().await, player.volume().await) else {
+                continue;
+            };
+
+            players.push(PlayerState {
+                id,
+                path,
+                volume,
+                elapsed: player.elapsed().await.ok(),
+            });
+        }
+
+        let json = serde_json::to_vec_pretty(&MusicState { players }).map_err(|_| PlayerError::UnableToSaveState)?;
+        tokio::fs::write(path, json)
+            .await
+            .map_err(|_| PlayerError::UnableToSaveState)?;
+
+        Ok(())
+    }
+
+    /// Loads many files at once, each given as the id of the player to load it into and the path
+    /// of the file, e.g. to preload a library. Up to `LOAD_MANY_CONCURRENCY` files are loaded at
+    /// the same time so the disk isn't kept seeking between all of them. Returns the result for
+    /// every entry in the order they were given, so one file failing doesn't stop the rest.
+    ///
+    /// Every file is prepared with the settings of the player it is for without locking anything
+    /// or opening an output stream, and only handed over once it is ready, so players keep playing
+    /// what they had while their next file loads. Players that don't exist yet are then created on
+    /// the blocking thread pool, since opening an output device can take a while, and entries with
+    /// the same id share one player. Either way the new tracks are left paused at the start.
+    pub async fn load_many(&self, entries: Vec<(String, String)>) -> Vec<(String, PlayerResult<()>)> {
+        let permits = Semaphore::new(LOAD_MANY_CONCURRENCY);
+        let mut loads: Vec<Load> = entries
+            .into_iter()
+            .map(|(id, path)| {
+                let permits = &permits;
+                Box::pin(async move {
+                    // The semaphore is never closed, so this always holds a permit.
+                    let _permit = permits.acquire().await;
+                    let result = self.load_into(&id, &path).await;
+                    (id, result)
+                }) as Load
+            })
+            .collect();
+
+        let mut results: Vec<Option<(PlayerId, PlayerResult<()>)>> = loads.iter().map(|_| None).collect();
+        std::future::poll_fn(|cx| {
+            for (load, result) in loads.iter_mut().zip(results.iter_mut()) {
+                if result.is_none() {
+                    if let Poll::Ready(output) = load.as_mut().poll(cx) {
+                        *result = Some(output);
+                    }
+                }
+            }
+
+            match results.iter().all(Option::is_some) {
+                true => Poll::Ready(()),
+                false => Poll::Pending,
+            }
+        })
+        .await;
+
+        results.into_iter().flatten().collect()
+    }
+
+    async fn load_into(&self, id: &str, path: &str) -> PlayerResult<()> {
+        let loader = match Self::handle(&self.players, id) {
+            Some(player) => player.track_loader().await?,
+            None => TrackLoader::new(PlayerConfig::default()),
+        };
+        let prepared = loader.prepare_path(path).await?;
+
+        // Another load may create the player while this one opens its output device, in which
+        // case both load into the one that was stored first.
+        let player = match self.get_player(id) {
+            Some(player) => player,
+            None => {
+                let spare = Player::blocking(Player::new).await?;
+                self.get_or_insert(id, PlayerHandle::spawn(spare))
+            }
+        };
+        let player = read_player(id, &player).clone();
+        player.pause().await?;
+        player.load_prepared(prepared).await
+    }
+
+    /// Recreates the players saved by `save_state`. Each one is loaded paused at its saved volume
+    /// and position, replacing any player with the same id. Restoring the position is best effort,
+    /// since not every file is seekable, but a file that can no longer be loaded stops the restore
+    /// with an error.
+    pub async fn load_state(&self, path: &str) -> PlayerResult<()> {
+        let json = tokio::fs::read(path).await.map_err(|_| PlayerError::UnableToLoadState)?;
+        let state: MusicState = serde_json::from_slice(&json).map_err(|_| PlayerError::UnableToLoadState)?;
+
+        for player_state in state.players {
+            let mut player = Player::blocking(Player::new).await?;
+            player.set_volume(player_state.volume);
+            player.pause();
+
+            if let Some(track_path) = player_state.path.as_deref() {
+                player.load_path(track_path).await?;
+                if let Some(elapsed) = player_state.elapsed {
+                    let _ = player.seek(elapsed);
+                }
+            }
+
+            self.add_player(player_state.id, PlayerHandle::spawn(player));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures;
+    use crate::mock::MockPlayer;
+
+    fn playing() -> MockPlayer {
+        MockPlayer::playing(Duration::from_secs(60))
+    }
+
+    /// The level a mock player would be heard at, its own volume times the mix level it was given.
+    fn heard_volume(music: &Music<MockPlayer>, key: &str) -> f32 {
+        music
+            .with_player_mut(key, |player| player.volume * player.master_volume)
+            .unwrap()
+    }
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!((actual - expected).abs() < 1e-6, "expected {expected}, got {actual}");
+    }
+
+    #[test]
+    fn clear_all_stops_and_removes_every_player() {
+        let music = Music::<MockPlayer>::new();
+        music.add_player("music".to_string(), playing());
+        music.add_player("sfx".to_string(), playing());
+        music.set_tag("sfx", "sfx").unwrap();
+        let held = music.get_player("music").unwrap();
+
+        music.clear_all();
+
+        assert!(music.is_empty());
+        assert_eq!(music.get_tag("sfx"), None);
+        // A reference held elsewhere keeps the player alive, but it no longer plays.
+        assert_eq!(held.read().unwrap().state(), PlaybackState::Stopped);
+    }
+
+    #[test]
+    fn volume_stacks_master_group_and_player() {
+        let music = Music::<MockPlayer>::new();
+        let mut sfx = playing();
+        sfx.set_volume(0.5);
+        music.add_player("sfx".to_string(), sfx);
+        music.add_player("music".to_string(), playing());
+        music.set_tag("sfx", "sfx").unwrap();
+
+        music.set_group_volume("sfx", 0.5);
+        music.set_master_volume(0.8);
+        assert_close(heard_volume(&music, "sfx"), 0.5 * 0.5 * 0.8);
+        assert_close(heard_volume(&music, "music"), 0.8);
+
+        // Players joining later pick up the levels already set.
+        music.add_player("click".to_string(), playing());
+        music.set_tag("click", "sfx").unwrap();
+        assert_close(heard_volume(&music, "click"), 0.5 * 0.8);
+
+        music.remove_tag("sfx");
+        assert_close(heard_volume(&music, "sfx"), 0.5 * 0.8);
+    }
+
+    #[test]
+    fn poisoned_player_is_recovered() {
+        let music = Music::<MockPlayer>::new();
+        music.add_player("music".to_string(), playing());
+        let player = music.get_player("music").unwrap();
+        let _ = std::thread::spawn(move || {
+            let _player = player.write().unwrap();
+            panic!("poisoning the player on purpose");
+        })
+        .join();
+        assert!(music.get_player("music").unwrap().is_poisoned());
+
+        music.pause_all();
+        assert_eq!(
+            music.with_player_mut("music", |player| player.state()),
+            Some(PlaybackState::Paused)
+        );
+        assert!(!music.get_player("music").unwrap().is_poisoned());
+    }
+
+    #[test]
+    fn players_can_be_added_and_removed() {
+        let music = Music::<MockPlayer>::new();
+        music.add_player("music".to_string(), playing());
+        music.add_player("sfx".to_string(), MockPlayer::new());
+        music.set_tag("sfx", "sfx").unwrap();
+        assert_eq!(music.len(), 2);
+        assert!(music.contains("sfx"));
+
+        music.remove_player("sfx");
+        assert!(!music.contains("sfx"));
+        assert!(music.get_player("sfx").is_none());
+        assert_eq!(music.get_tag("sfx"), None);
+        assert_eq!(music.set_tag("sfx", "sfx"), Err(PlayerError::PlayerNotFound));
+        assert_eq!(music.len(), 1);
+    }
+
+    #[test]
+    fn exclusive_playback_pauses_the_other_players() {
+        let music = Music::<MockPlayer>::with_exclusive_playback();
+        for key in ["album", "podcast", "click"] {
+            music.add_player(key.to_string(), playing());
+        }
+        music.set_tag("click", "sfx").unwrap();
+        music.set_exclusive_exempt("sfx", true);
+        music.with_player_mut("podcast", MockPlayer::pause);
+
+        music.play("podcast").unwrap();
+        let state = |key| music.with_player_mut(key, |player| player.state()).unwrap();
+        assert_eq!(state("podcast"), PlaybackState::Playing);
+        assert_eq!(state("album"), PlaybackState::Paused);
+        assert_eq!(state("click"), PlaybackState::Playing);
+
+        music.set_exclusive(false);
+        music.play("album").unwrap();
+        assert_eq!(state("podcast"), PlaybackState::Playing);
+        assert_eq!(music.play("missing"), Err(PlayerError::PlayerNotFound));
+    }
+
+    #[test]
+    fn drain_finished_removes_only_the_finished_players() {
+        let music = Music::<MockPlayer>::new();
+        music.add_player("music".to_string(), playing());
+        music.add_player(
+            "click".to_string(),
+            MockPlayer {
+                state: PlaybackState::Finished,
+                ..playing()
+            },
+        );
+
+        assert_eq!(music.drain_finished(), vec!["click".to_string()]);
+        assert!(music.contains("music"));
+        assert!(!music.contains("click"));
+        assert!(music.drain_finished().is_empty());
+    }
+
+    #[test]
+    fn crossfading_hands_playback_to_the_other_player() {
+        let music = Music::<MockPlayer>::new();
+        music.add_player("album".to_string(), playing());
+        music.add_player("podcast".to_string(), playing());
+        music.with_player_mut("podcast", MockPlayer::pause);
+
+        music.crossfade_to("album", "podcast", Duration::from_secs(2)).unwrap();
+
+        let player = |key| music.with_player_mut(key, |player| player.clone()).unwrap();
+        assert_eq!(player("album").state, PlaybackState::Paused);
+        assert_eq!(player("podcast").state, PlaybackState::Playing);
+        assert_eq!((player("album").fades, player("podcast").fades), (1, 1));
+        assert_eq!(
+            music.crossfade_to("album", "missing", Duration::from_secs(2)),
+            Err(PlayerError::PlayerNotFound)
+        );
+    }
+
+    #[test]
+    fn batch_operations_reach_every_player() {
+        let music = Music::<MockPlayer>::new();
+        music.add_player("album".to_string(), playing());
+        music.add_player("podcast".to_string(), playing());
+        music.add_player(
+            "lost".to_string(),
+            MockPlayer {
+                state: PlaybackState::DeviceLost,
+                ..playing()
+            },
+        );
+        let state = |key| music.with_player_mut(key, |player| player.state()).unwrap();
+
+        music.pause_all();
+        assert_eq!(
+            (state("album"), state("podcast")),
+            (PlaybackState::Paused, PlaybackState::Paused)
+        );
+
+        // A player that can't start doesn't keep the others from starting.
+        music.play_all();
+        assert_eq!(
+            (state("album"), state("podcast")),
+            (PlaybackState::Playing, PlaybackState::Playing)
+        );
+        assert_eq!(state("lost"), PlaybackState::DeviceLost);
+
+        music.stop_all();
+        assert_eq!(
+            (state("album"), state("podcast")),
+            (PlaybackState::Stopped, PlaybackState::Stopped)
+        );
+        assert_eq!(music.len(), 3);
+    }
+
+    #[test]
+    fn adding_past_the_capacity_evicts_the_oldest_player() {
+        let music = Music::<MockPlayer>::with_capacity(2);
+        music.add_player("first".to_string(), playing());
+        let first = music.get_player("first").unwrap();
+        for key in ["second", "third"] {
+            // Access times are compared, so keep them apart on coarse clocks.
+            std::thread::sleep(Duration::from_millis(2));
+            music.add_player(key.to_string(), playing());
+        }
+
+        assert_eq!(music.len(), 2);
+        assert!(!music.contains("first"));
+        assert!(music.contains("second") && music.contains("third"));
+        assert_eq!(first.read().unwrap().state(), PlaybackState::Stopped);
+    }
+
+    #[test]
+    fn eviction_spares_a_player_that_was_looked_up() {
+        let music = Music::<MockPlayer>::with_capacity(3);
+        for key in ["first", "second", "third"] {
+            music.add_player(key.to_string(), playing());
+            // Access times are compared, so keep them apart on coarse clocks.
+            std::thread::sleep(Duration::from_millis(2));
+        }
+        // Taken straight from the map, since looking it up would count as using it.
+        let second = Arc::clone(music.players.get("second").unwrap().value());
+
+        // Using the oldest player leaves "second" as the least recently used.
+        assert!(music.get_player("first").is_some());
+        std::thread::sleep(Duration::from_millis(2));
+        music.add_player("fourth".to_string(), playing());
+
+        assert_eq!(music.len(), 3);
+        assert!(!music.contains("second"));
+        assert_eq!(second.read().unwrap().state(), PlaybackState::Stopped);
+        for key in ["first", "third", "fourth"] {
+            assert_eq!(
+                music.with_player_mut(key, |player| player.state()),
+                Some(PlaybackState::Playing)
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn loads_of_the_same_new_id_share_one_player() {
+        if fixtures::output_player().is_none() {
+            return;
+        }
+        let music = Music::new();
+        let short = fixtures::silent_mp3("load-many-short", 1);
+        let long = fixtures::silent_mp3("load-many-long", 2);
+        let entries = [short, long].map(|path| ("album".to_string(), path.to_str().unwrap().to_string()));
+
+        let results = music.load_many(entries.to_vec()).await;
+
+        assert!(results.iter().all(|(_, result)| result.is_ok()), "{results:?}");
+        assert_eq!(music.len(), 1);
+        let player = music.get_player("album").unwrap().read().unwrap().clone();
+        assert_eq!(player.state().await, Ok(PlaybackState::Paused));
+    }
+
+    #[tokio::test]
+    async fn moving_a_source_hands_the_track_between_player_threads() {
+        if fixtures::output_player().is_none() {
+            return;
+        }
+        let music = Music::new();
+        let path = fixtures::silent_mp3("move-source", 2);
+        let entries = vec![("speakers".to_string(), path.to_str().unwrap().to_string())];
+        assert!(music.load_many(entries).await[0].1.is_ok());
+        music.get_or_create("headphones").unwrap();
+
+        music.move_source("speakers", "headphones").unwrap();
+
+        let player = |key| music.get_player(key).unwrap().read().unwrap().clone();
+        assert_eq!(player("speakers").state().await, Ok(PlaybackState::Empty));
+        assert_eq!(player("headphones").state().await, Ok(PlaybackState::Paused));
+        assert_eq!(player("headphones").path().await, Ok(path.to_str().map(String::from)));
+    }
+
+    #[test]
+    fn a_failed_crossfade_leaves_the_old_player_playing() {
+        let music = Music::<MockPlayer>::new();
+        music.add_player("album".to_string(), playing());
+        music.add_player(
+            "podcast".to_string(),
+            MockPlayer {
+                state: PlaybackState::DeviceLost,
+                ..playing()
+            },
+        );
+
+        let fade = Duration::from_secs(1);
+        assert_eq!(music.crossfade_to("album", "podcast", fade), Err(PlayerError::DeviceLost));
+        let album = music.with_player_mut("album", |player| player.clone()).unwrap();
+        assert_eq!(album.state, PlaybackState::Playing);
+        assert_eq!(album.fades, 0);
+
+        // A player crossfaded into itself isn't faded at all.
+        music.crossfade_to("album", "album", fade).unwrap();
+        assert_eq!(music.with_player_mut("album", |player| player.fades), Some(0));
+    }
 }
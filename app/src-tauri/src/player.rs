@@ -1,188 +1,3842 @@
 use std::{
-    io::{BufReader, Seek},
-    time::Duration,
+    collections::VecDeque,
+    future::Future,
+    io::Seek,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex, PoisonError,
+    },
+    task::Poll,
+    time::{Duration, Instant},
 };
 
-use rodio::{Decoder, OutputStream, Sink};
+use rodio::{
+    cpal::traits::{DeviceTrait, HostTrait},
+    OutputStream, Sink, Source,
+};
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
+
+use crate::cache::{self, CacheKey};
+use crate::decoder::{AudioDecoder, DecoderSettings};
+use crate::equalizer::{Equalizer, SharedEqualizer};
+#[cfg(feature = "symphonia")]
+use crate::media::SYMPHONIA_FORMATS;
+use crate::media::{self, MediaHandle, SUPPORTED_FORMATS};
+use crate::metadata::{self, CoverArt, TrackMetadata};
+use crate::mp3::{self, SparseIndex};
+use crate::sources::{
+    Amplified, Equalized, Levels, Metered, NowPlaying, Panned, Repeating, Resampled, Signalled, Silence, TrackClock, Upmixed,
+};
+use crate::trace;
 
-#[derive(PartialEq, Eq)]
+/// Variants that come from an I/O error carry its kind, and decoding errors carry the decoder's
+/// message, so callers can tell why something failed. The messages are meant to be shown to the
+/// user as they are.
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
 pub enum PlayerError {
-    UnableToCloneFileHandle,
+    #[error("couldn't clone the file handle: {0}")]
+    UnableToCloneFileHandle(std::io::ErrorKind),
+    #[error("no file is loaded")]
     NoFileHandle,
+    #[error("the track has no seek index")]
     NoSeekIndex,
-    NotAbleToSeek,
+    #[error("couldn't seek in the file: {0}")]
+    NotAbleToSeek(std::io::ErrorKind),
+    #[error("couldn't create a seek index")]
     UnableToCreateSeekIndex,
+    #[error("couldn't get the duration")]
     UnableToGetDuration,
-    UnableToOpenFile,
+    #[error("couldn't open {path:?}: {kind}")]
+    UnableToOpenFile { path: String, kind: std::io::ErrorKind },
+    #[error("no output device could be opened")]
+    NoOutputDevice,
+    #[error("couldn't create a sink on the output device")]
+    UnableToCreateSink,
+    /// The player is in `PlaybackState::DeviceLost`. Nothing can be loaded, seeked or started
+    /// until `Player::recover_device()` or `Player::switch_device()` opens another device.
+    #[error("the output device was lost")]
+    DeviceLost,
+    #[error("the speed must be above zero")]
+    InvalidSpeed,
+    #[error("the balance must be between -1.0 and 1.0")]
+    InvalidBalance,
+    #[error("the volume must be between 0.0 and 1.0")]
+    InvalidVolume,
+    #[error("the loop must start before it ends, and end within the track")]
+    InvalidLoop,
+    #[error("couldn't download the file: {0}")]
+    NetworkError(String),
+    #[error("couldn't decode the file: {0}")]
+    UnableToDecodeFile(String),
+    /// The file isn't in any of the `supported` formats. `extension` is the file's own extension,
+    /// or empty when it was loaded without a path or has no extension.
+    #[error("unsupported format {extension:?}, expected one of {}", .supported.join(", "))]
+    UnsupportedFormat {
+        extension: String,
+        supported: &'static [&'static str],
+    },
+    #[error("couldn't read the file's metadata: {0}")]
+    UnableToReadMetadata(std::io::ErrorKind),
+    #[error("couldn't save the state")]
+    UnableToSaveState,
+    #[error("couldn't load the saved state")]
+    UnableToLoadState,
+    #[error("couldn't clear the cache")]
+    UnableToClearCache,
+    #[error("couldn't find the resource")]
+    UnableToResolveResource,
+    #[error("the playlist is empty")]
+    EmptyPlaylist,
+    #[error("loading was cancelled")]
+    LoadCancelled,
+    #[error("no player has that id")]
+    PlayerNotFound,
+    #[error("a player with that id already exists")]
+    PlayerAlreadyExists,
+    /// The thread a `PlayerHandle` sends its commands to is gone.
+    #[error("the player thread has stopped")]
+    PlayerThreadStopped,
+    /// `PlayerConfig::verify_on_load` found the file to be cut short or undecodable, roughly
+    /// `at_byte` bytes in.
+    #[error("the file is corrupt or truncated around byte {at_byte}")]
+    CorruptFile { at_byte: u64 },
+}
+
+impl PlayerError {
+    pub(crate) fn unable_to_open(path: &str, error: std::io::Error) -> Self {
+        Self::UnableToOpenFile {
+            path: path.to_string(),
+            kind: error.kind(),
+        }
+    }
+
+    /// A machine-readable name for the kind of error, which stays the same across releases even
+    /// when the message changes, for the front-end to match on.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::UnableToCloneFileHandle(_) => "unable_to_clone_file_handle",
+            Self::NoFileHandle => "no_file_handle",
+            Self::NoSeekIndex => "no_seek_index",
+            Self::NotAbleToSeek(_) => "not_able_to_seek",
+            Self::UnableToCreateSeekIndex => "unable_to_create_seek_index",
+            Self::UnableToGetDuration => "unable_to_get_duration",
+            Self::UnableToOpenFile { .. } => "unable_to_open_file",
+            Self::NoOutputDevice => "no_output_device",
+            Self::UnableToCreateSink => "unable_to_create_sink",
+            Self::DeviceLost => "device_lost",
+            Self::InvalidSpeed => "invalid_speed",
+            Self::InvalidBalance => "invalid_balance",
+            Self::InvalidVolume => "invalid_volume",
+            Self::InvalidLoop => "invalid_loop",
+            Self::NetworkError(_) => "network_error",
+            Self::UnableToDecodeFile(_) => "unable_to_decode_file",
+            Self::UnsupportedFormat { .. } => "unsupported_format",
+            Self::UnableToReadMetadata(_) => "unable_to_read_metadata",
+            Self::UnableToSaveState => "unable_to_save_state",
+            Self::UnableToLoadState => "unable_to_load_state",
+            Self::UnableToClearCache => "unable_to_clear_cache",
+            Self::UnableToResolveResource => "unable_to_resolve_resource",
+            Self::EmptyPlaylist => "empty_playlist",
+            Self::LoadCancelled => "load_cancelled",
+            Self::PlayerNotFound => "player_not_found",
+            Self::PlayerAlreadyExists => "player_already_exists",
+            Self::PlayerThreadStopped => "player_thread_stopped",
+            Self::CorruptFile { .. } => "corrupt_file",
+        }
+    }
+}
+
+/// Serializes as `{ "code": ..., "message": ... }`, with the `code()` and the `Display` message,
+/// which is what a Tauri command returning `PlayerResult` hands the front-end.
+impl Serialize for PlayerError {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let mut error = serializer.serialize_struct("PlayerError", 2)?;
+        error.serialize_field("code", self.code())?;
+        error.serialize_field("message", &self.to_string())?;
+        error.end()
+    }
 }
 
 pub type PlayerResult<T> = std::result::Result<T, PlayerError>;
 
+pub type FinishedCallback = Box<dyn Fn() + Send + Sync>;
+
+pub type DeviceLostCallback = Box<dyn Fn() + Send + Sync>;
+
 const MAX_FILE_SIZE_FOR_SEEK_INDEX: u64 = 1024 * 1024 * 50; // 50 MB
 
-/// A player manages audio functions for a file. Things like play, pause, resume, seek, volume.
-pub struct Player {
-    sink: Sink,
-    _stream: OutputStream,
-    file_handle: Option<std::fs::File>,
-    duration: Option<Duration>,
-    seek_index: Option<Vec<(Duration, u64)>>,
+/// The same size `BufReader` picks by default.
+const DEFAULT_READ_BUFFER_SIZE: usize = 8 * 1024;
+
+/// How often a fade updates the sink volume.
+const FADE_STEP: Duration = Duration::from_millis(10);
+
+/// How much quieter the bottom of the logarithmic volume curve is than full volume.
+const LOGARITHMIC_VOLUME_RANGE_DB: f32 = 60.0;
+
+/// How often the output thread checks that its device is still there.
+const DEVICE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The shortest interval `subscribe_progress()` sends updates at.
+const MIN_PROGRESS_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How many progress updates can pile up before new ones are dropped.
+const PROGRESS_CHANNEL_CAPACITY: usize = 16;
+
+/// How often `wait_until_end_async()` checks whether the sink has run dry.
+const WAIT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// The most normalization will boost a quiet track by, so near-silent tracks don't get their
+/// noise floor blown up.
+const MAX_NORMALIZATION_GAIN_DB: f32 = 12.0;
+
+/// How many frames each peak is taken over when computing a waveform, before the peaks are
+/// combined into the requested number of buckets.
+const WAVEFORM_CHUNK_FRAMES: usize = 256;
+
+/// The peak level in dBFS below which the ends of a track count as silence.
+const DEFAULT_SILENCE_THRESHOLD_DB: f32 = -50.0;
+
+/// The format silence is played in when there is no track to take it from.
+const DEFAULT_SILENCE_FORMAT: AudioFormat = AudioFormat {
+    sample_rate: 44100,
+    channels: 2,
+};
+
+/// How much shorter than its known duration a file may decode to before `verify_on_load` counts it
+/// as cut short, since durations from tags and headers are only approximate.
+const VERIFY_DURATION_TOLERANCE: Duration = Duration::from_secs(1);
+
+/// How long a player that is dropped while playing takes to ramp down to silence, and how many
+/// steps it does it in. Short enough that dropping a player never noticeably blocks.
+const DROP_FADE: Duration = Duration::from_millis(30);
+const DROP_FADE_STEPS: u32 = 6;
+
+/// How long the sleep timer fades out for before it pauses.
+const SLEEP_TIMER_FADE: Duration = Duration::from_secs(5);
+
+const MIN_SPEED: f32 = 0.25;
+const MAX_SPEED: f32 = 4.0;
+
+/// Settings that are fixed when a player is created.
+#[derive(Debug, Clone)]
+pub struct PlayerConfig {
+    /// Files larger than this many bytes get no seek index up front, since building one would take
+    /// too long. MP3s are still seekable, by scanning their frame headers as seeks need them, which
+    /// makes seeking further into the file than before slower, by roughly the time it takes to
+    /// read through everything in between once.
+    pub max_seek_index_file_size: u64,
+    /// Whether to build seek indexes at all. Turning this off saves memory but makes every file
+    /// unseekable.
+    pub enable_seek_index: bool,
+    /// Keeps only one seek index entry per this much audio. Seeks snap to the closest entry at or
+    /// before the target, so a coarser index uses less memory but lands further from it. `None`
+    /// keeps an entry for every frame, which is as fine as the analyzer goes.
+    pub seek_granularity: Option<Duration>,
+    /// How many bytes the decoder reads from the file at a time. Small buffers get short sounds
+    /// going sooner after a load or seek, large ones mean fewer reads while streaming a long
+    /// track. Samples are decoded as the output asks for them, so there is no decode-ahead buffer
+    /// to size, and rodio doesn't expose the output device's own buffer.
+    pub read_buffer_size: usize,
+    /// Plays mono tracks as stereo from the start of the processing chain, so the equalizer,
+    /// normalization and levels all work on two channels and `channel_layout()` reports stereo.
+    /// Balance works on mono tracks either way, since panning upmixes them on its own.
+    pub force_stereo: bool,
+    pub decoder_backend: DecoderBackend,
+    /// The peak level in dBFS that the player starts out treating anything quieter than as
+    /// silence, see `Player::set_silence_threshold()`.
+    pub silence_threshold: f32,
+    /// Decodes every file all the way through while loading it, failing with
+    /// `PlayerError::CorruptFile` if it is truncated or stops decoding before its end. Without
+    /// this, such files load fine and only stop playing partway through. Loading takes about as
+    /// long as decoding the whole file.
+    pub verify_on_load: bool,
+    /// Decodes a file all the way through to count its samples when its duration can't be read
+    /// or estimated any other way, which takes about as long as `verify_on_load`.
+    pub decode_for_duration: bool,
+    /// The volume the player starts at, from `0.0` to `1.0`, so it can start muted or at a
+    /// remembered level without playing at full volume first.
+    pub initial_volume: f32,
+    pub initial_repeat_mode: RepeatMode,
+    /// The playback speed the player starts at, clamped to `0.25..=4.0` like `set_speed()`.
+    pub initial_speed: f32,
+    /// The pan the player starts at, from `-1.0` to `1.0` like `set_balance()`.
+    pub initial_balance: f32,
+    pub initial_equalizer: Equalizer,
 }
 
-impl Player {
-    pub fn new() -> Self {
-        let (_stream, stream_handle) = OutputStream::try_default().unwrap();
-        let sink = Sink::try_new(&stream_handle).unwrap();
+impl Default for PlayerConfig {
+    fn default() -> Self {
         Self {
-            sink,
-            _stream,
-            file_handle: None,
-            duration: None,
-            seek_index: None,
+            max_seek_index_file_size: MAX_FILE_SIZE_FOR_SEEK_INDEX,
+            enable_seek_index: true,
+            seek_granularity: None,
+            read_buffer_size: DEFAULT_READ_BUFFER_SIZE,
+            force_stereo: false,
+            decoder_backend: DecoderBackend::default(),
+            silence_threshold: DEFAULT_SILENCE_THRESHOLD_DB,
+            verify_on_load: false,
+            decode_for_duration: false,
+            initial_volume: 1.0,
+            initial_repeat_mode: RepeatMode::Off,
+            initial_speed: 1.0,
+            initial_balance: 0.0,
+            initial_equalizer: Equalizer::default(),
         }
     }
+}
 
-    pub fn is_file_loaded(&self) -> bool {
-        self.file_handle.is_some()
+/// Which library decodes the audio.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DecoderBackend {
+    /// rodio's own decoders, which play the `SUPPORTED_FORMATS`.
+    #[default]
+    Rodio,
+    /// Symphonia, which also plays AAC, on its own or in M4A files. It can seek in files without
+    /// a seek index too, landing on the exact sample, which makes M4A files as well as the ones
+    /// loaded from memory seekable. Needs the `symphonia` feature.
+    ///
+    /// Symphonia doesn't decode Opus yet, so Ogg files need to hold Vorbis like with rodio.
+    #[cfg(feature = "symphonia")]
+    Symphonia,
+}
+
+impl DecoderBackend {
+    /// The extensions of the formats the backend can decode.
+    pub fn formats(self) -> &'static [&'static str] {
+        match self {
+            Self::Rodio => SUPPORTED_FORMATS,
+            #[cfg(feature = "symphonia")]
+            Self::Symphonia => SYMPHONIA_FORMATS,
+        }
     }
 
-    pub async fn load_path(&mut self, path: &str) -> PlayerResult<()> {
+    /// Whether the backend's decoders can seek on their own, which tracks without a seek index
+    /// rely on.
+    fn can_seek(self) -> bool {
+        match self {
+            Self::Rodio => false,
+            #[cfg(feature = "symphonia")]
+            Self::Symphonia => true,
+        }
+    }
+}
+
+/// Controls what happens once the loaded track reaches its end.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RepeatMode {
+    /// Stop when the track ends.
+    Off,
+    /// Start the current track over.
+    One,
+    /// Start everything loaded into the player over. Tracks queued with `enqueue_path()` are
+    /// still repeated one at a time for now, the same as `One`.
+    All,
+}
+
+/// What a player is currently doing, as seen from the outside.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum PlaybackState {
+    /// Nothing has been loaded yet, or the file was let go of with `unload()`.
+    Empty,
+    Playing,
+    Paused,
+    /// Playback was stopped with `stop()`. The track is still loaded and `play()` starts it over.
+    Stopped,
+    /// The loaded track, and everything queued after it, played through to the end.
+    Finished,
+    /// The output device went away, e.g. because headphones were unplugged, so nothing can be
+    /// heard until `recover_device()` or `switch_device()` moves playback to another one.
+    DeviceLost,
+}
+
+/// How the `0.0..=1.0` volume set on a player maps to the amplitude of its output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VolumeCurve {
+    /// The volume is used as the amplitude directly.
+    Linear,
+    /// The volume is spread over a 60 dB range, which sounds even to human hearing.
+    Logarithmic,
+}
+
+impl VolumeCurve {
+    fn amplitude(self, volume: f32) -> f32 {
+        match self {
+            VolumeCurve::Linear => volume,
+            VolumeCurve::Logarithmic if volume <= 0.0 => 0.0,
+            VolumeCurve::Logarithmic => 10f32.powf((volume - 1.0) * LOGARITHMIC_VOLUME_RANGE_DB / 20.0),
+        }
+    }
+}
+
+/// A consistent snapshot of how far playback has got, for rendering a progress bar.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PlaybackProgress {
+    pub elapsed: Duration,
+    pub total: Option<Duration>,
+    /// `elapsed` as a fraction of `total`, only known when the duration is.
+    pub fraction: Option<f32>,
+}
+
+impl PlaybackProgress {
+    fn new(elapsed: Duration, total: Option<Duration>) -> Self {
+        let fraction = match total {
+            Some(total) if !total.is_zero() => Some((elapsed.as_secs_f32() / total.as_secs_f32()).min(1.0)),
+            _ => None,
+        };
+
+        Self {
+            elapsed,
+            total,
+            fraction,
+        }
+    }
+}
+
+/// Everything about a player worth showing on a debug overlay, gathered in one go by
+/// `Player::diagnostics()`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlayerDiagnostics {
+    pub loaded: bool,
+    pub seekable: bool,
+    pub has_duration: bool,
+    /// Whether the track has a title, artist or album in its tags.
+    pub has_metadata: bool,
+    pub queue_len: usize,
+    pub volume: f32,
+    pub state: PlaybackState,
+    pub format: Option<AudioFormat>,
+}
+
+/// What `Player::probe()` found out about a file without loading it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrackProbe {
+    /// The extension of the format the file is in, as recognised from its first bytes.
+    pub format: &'static str,
+    pub audio_format: AudioFormat,
+    /// The duration from the tags, or worked out the way loading the file would.
+    pub duration: Option<Duration>,
+    /// Whether `duration` is only an estimate, see `Player::duration_is_estimated()`.
+    pub duration_estimated: bool,
+    pub metadata: TrackMetadata,
+    /// Whether a player with the default config could seek in the file once it is loaded.
+    pub seekable: bool,
+}
+
+/// Where a track was loaded from, so `reload()` can load it again and the app can show or save
+/// what is loaded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PlayerSource {
+    /// A file loaded by its path, including bundled resources.
+    Path(String),
+    Url(String),
+    /// A file handle handed over by the caller with `load_file()`, which is reused as it is.
+    File,
+    /// Audio handed over by the caller with `load_bytes()`.
+    Bytes,
+    /// A gap queued with `append_silence()`.
+    Silence,
+}
+
+/// An ordered list of files for `Player::play_playlist()`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Playlist {
+    pub paths: Vec<String>,
+}
+
+impl Playlist {
+    pub fn new(paths: Vec<String>) -> Self {
+        Self { paths }
+    }
+}
+
+/// The layout of the decoded samples of a track.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AudioFormat {
+    pub sample_rate: u32,
+    pub channels: u16,
+}
+
+impl AudioFormat {
+    fn of<S: Source<Item = i16>>(source: &S) -> Self {
+        Self {
+            sample_rate: source.sample_rate(),
+            channels: source.channels(),
+        }
+    }
+}
+
+/// How many channels a track has, as far as playing it goes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ChannelLayout {
+    Mono,
+    Stereo,
+    /// Surround or other layouts with more than two channels.
+    Multi(u16),
+}
+
+impl ChannelLayout {
+    fn of(channels: u16) -> Self {
+        match channels {
+            0 | 1 => ChannelLayout::Mono,
+            2 => ChannelLayout::Stereo,
+            channels => ChannelLayout::Multi(channels),
+        }
+    }
+}
+
+/// Trims the silence of one track, see `Player::silence_trim()`. It is handed a check for whether
+/// its result is still wanted.
+type SilenceTrim = Box<dyn FnOnce(&dyn Fn() -> bool) + Send>;
+
+/// A file loaded into the player, either the one playing or one queued up behind it.
+struct Track {
+    file_handle: MediaHandle,
+    origin: PlayerSource,
+    seek_index: Option<Vec<(Duration, u64)>>,
+    /// Stands in for the seek index of MP3s that are too large to index up front.
+    sparse_index: Option<SparseIndex>,
+    /// Whether seeks go through the decoder, for tracks with neither index when the backend can
+    /// seek on its own.
+    decoder_seeks: bool,
+    /// Whether the duration in the clock and the tags is only an estimate, see
+    /// `duration_is_estimated()`.
+    duration_estimated: bool,
+    clock: Arc<TrackClock>,
+    metadata: TrackMetadata,
+    cover_art: Option<CoverArt>,
+    /// Filled in once the track has been opened for decoding.
+    format: Option<AudioFormat>,
+    /// Where the track is in the playlist it was loaded from, if any.
+    playlist_index: Option<usize>,
+    /// The RMS level of the track in dBFS, once it has been measured for normalization.
+    loudness: Option<f32>,
+    /// The normalization gain as the bits of an `f32`, shared with the track's sources.
+    gain: Arc<AtomicU32>,
+}
+
+/// The settings a player loads tracks with, taken from it by `Player::track_loader()`, so files
+/// can be analyzed for it without borrowing it. `Music` uses this to load many files at once
+/// without opening an output stream or locking a player for each of them.
+#[derive(Debug, Clone)]
+pub struct TrackLoader {
+    config: PlayerConfig,
+    normalization_target: Option<f32>,
+    skip_silence: bool,
+    silence_threshold: f32,
+}
+
+/// A file that has been analyzed and opened for decoding, ready to be handed to
+/// `Player::load_prepared()`.
+pub struct PreparedTrack {
+    track: Track,
+    media: MediaHandle,
+}
+
+/// A track taken out of a player by `Player::hand_over_track()`, ready to be handed to
+/// `Player::take_over_track()` on another one.
+pub struct HandedOverTrack {
+    track: Track,
+    elapsed: Option<Duration>,
+    paused: bool,
+}
+
+impl TrackLoader {
+    /// Loads tracks like a new player with `config` would, with normalization and silence
+    /// skipping off.
+    pub fn new(config: PlayerConfig) -> Self {
+        Self {
+            silence_threshold: config.silence_threshold,
+            config,
+            normalization_target: None,
+            skip_silence: false,
+        }
+    }
+
+    /// Does all the work of loading the file at `path`, like analyzing it, building its seek
+    /// index and reading its tags, so that `Player::load_prepared()` only has to start decoding
+    /// it. Fails like `Player::load_path()` would.
+    pub async fn prepare_path(&self, path: &str) -> PlayerResult<PreparedTrack> {
         let file = match tokio::fs::File::open(path).await {
             Ok(file) => file,
-            Err(_) => return Err(PlayerError::UnableToOpenFile),
+            Err(error) => return Err(PlayerError::unable_to_open(path, error)),
         };
 
-        self.load_file(file).await?;
+        self.prepare(file, Some(path)).await
+    }
 
-        Ok(())
+    /// Gets audio in memory ready to play, returning the track along with its decoder and the
+    /// handle repeats decode from. Nothing here waits on the disk, but verifying, measuring or
+    /// trimming the track decodes all of it, so callers run this on the blocking pool.
+    fn prepare_memory(&self, data: Vec<u8>, origin: PlayerSource) -> PlayerResult<(Track, AudioDecoder, MediaHandle)> {
+        let mut media = MediaHandle::from_bytes(data);
+        let format = media::sniff_reader(&media)
+            .map_err(|error| PlayerError::NotAbleToSeek(error.kind()))?
+            .filter(|format| self.config.decoder_backend.formats().contains(format));
+        if format.is_none() {
+            let url = match &origin {
+                PlayerSource::Url(url) => url.split(['?', '#']).next(),
+                _ => None,
+            };
+            return Err(Player::unsupported_format(self.config.decoder_backend, url));
+        }
+
+        let (mut tags, cover_art) = metadata::read_tags(&media);
+        if let (true, Some(verify_handle)) = (self.config.verify_on_load, media.independent_clone()) {
+            Player::verify_media(verify_handle, format, tags.duration, self.config.decoder_backend)?;
+        }
+        media
+            .seek(std::io::SeekFrom::Start(0))
+            .map_err(|error| PlayerError::NotAbleToSeek(error.kind()))?;
+
+        let file_handle = media
+            .try_clone()
+            .map_err(|error| PlayerError::UnableToCloneFileHandle(error.kind()))?;
+        let repeat_file_handle = media
+            .try_clone()
+            .map_err(|error| PlayerError::UnableToCloneFileHandle(error.kind()))?;
+        let loudness = match (self.normalization_target, media.independent_clone()) {
+            (Some(_), Some(analysis_handle)) => Player::rms_loudness(analysis_handle, self.config.decoder_backend),
+            _ => None,
+        };
+
+        let decoder_seeks = Player::decoder_seeks(self.config.decoder_backend, false);
+        let settings = DecoderSettings {
+            backend: self.config.decoder_backend,
+            read_buffer_size: self.config.read_buffer_size,
+            seeks: decoder_seeks,
+        };
+        // The analyzer only reads files, so the duration has to come from the decoder or the tags.
+        let source = AudioDecoder::new(media, settings)?;
+        tags.duration = source.total_duration().or(tags.duration);
+        let mut duration_estimated = false;
+        if let (None, Some(fallback_handle)) = (tags.duration, file_handle.independent_clone()) {
+            (tags.duration, duration_estimated) = Player::fallback_duration(&self.config, fallback_handle, format);
+        }
+
+        let track = Track {
+            file_handle,
+            origin,
+            seek_index: None,
+            sparse_index: None,
+            decoder_seeks,
+            duration_estimated,
+            clock: Arc::new(TrackClock::new(tags.duration)),
+            metadata: tags,
+            cover_art,
+            format: Some(AudioFormat::of(&source)),
+            playlist_index: None,
+            loudness,
+            gain: Arc::new(AtomicU32::new(
+                Player::normalization_gain(self.normalization_target, loudness).to_bits(),
+            )),
+        };
+        if self.skip_silence {
+            Player::trim_silence(&track, self.config.decoder_backend, self.silence_threshold);
+        }
+
+        Ok((track, source, repeat_file_handle))
     }
 
-    pub async fn load_file(&mut self, file: tokio::fs::File) -> PlayerResult<()> {
-        self.stop();
+    async fn prepare(&self, file: tokio::fs::File, path: Option<&str>) -> PlayerResult<PreparedTrack> {
+        let mut sniff_file = file
+            .try_clone()
+            .await
+            .map_err(|error| PlayerError::UnableToCloneFileHandle(error.kind()))?
+            .into_std()
+            .await;
+        let format = media::sniff_reader(&mut sniff_file)
+            .map_err(|error| PlayerError::NotAbleToSeek(error.kind()))?
+            .filter(|format| self.config.decoder_backend.formats().contains(format));
+        if format.is_none() {
+            return Err(Player::unsupported_format(self.config.decoder_backend, path));
+        }
 
-        let reader = tokio::io::BufReader::new(file.try_clone().await.unwrap());
+        let analyzer_file = file
+            .try_clone()
+            .await
+            .map_err(|error| PlayerError::UnableToCloneFileHandle(error.kind()))?;
+        let reader = tokio::io::BufReader::new(analyzer_file);
         let mut analyzer = vpr_audio_analyzer::Analyzer::new(reader);
 
-        self.duration = match analyzer.get_duration().await {
+        let analyzed_duration = match analyzer.get_duration().await {
+            // The analyzer finds no MP3 frames in other formats and reports them as empty.
+            Ok(duration) if duration.is_zero() => None,
             Ok(duration) => Some(duration),
-            Err(_) => None,
+            Err(error) => {
+                trace::debug!("the analyzer couldn't read the duration", path = path, error = error);
+                None
+            }
         };
 
         // If the file is too big, we don't want to create a seek index
         // because it would take too long.
-        let file_size = file.metadata().await.unwrap().len();
-        if file_size <= MAX_FILE_SIZE_FOR_SEEK_INDEX {
-            self.seek_index = match analyzer.get_seek_index().await {
-                Ok(seek_index) => Some(seek_index),
-                Err(_) => None,
-            };
+        let metadata = file
+            .metadata()
+            .await
+            .map_err(|error| PlayerError::UnableToReadMetadata(error.kind()))?;
+        let cache_key = path.and_then(|path| CacheKey::new(path, &metadata));
+        let too_large = metadata.len() > self.config.max_seek_index_file_size;
+        let seek_index = match self.config.enable_seek_index && !too_large {
+            true => Player::build_seek_index(&mut analyzer, cache_key.as_ref()).await,
+            false => None,
+        };
+        if self.config.enable_seek_index && too_large {
+            trace::debug!(
+                "file too large for a seek index",
+                path = path,
+                size = metadata.len(),
+                max_size = self.config.max_seek_index_file_size,
+            );
         }
+        let sparse_index = match self.config.enable_seek_index && too_large && format == Some("mp3") {
+            true => Some(SparseIndex::default()),
+            false => None,
+        };
+        let seek_index = match self.config.seek_granularity {
+            Some(granularity) => seek_index.map(|seek_index| Player::thin_seek_index(seek_index, granularity)),
+            None => seek_index,
+        };
 
-        let mut std_file = file.into_std().await;
-        self.file_handle = match std_file.try_clone() {
-            Ok(std_file_handle) => Some(std_file_handle),
-            Err(_) => return Err(PlayerError::UnableToCloneFileHandle),
+        // Reading the tags, working out a duration neither the analyzer nor the tags know and
+        // verifying the file are blocking reads, and the last two can decode all of it.
+        let std_file = file.into_std().await;
+        let config = self.config.clone();
+        let owned_path = path.map(str::to_string);
+        let (std_file, tags, cover_art, duration_estimated) = Player::blocking(move || {
+            let mut std_file = std_file;
+            std_file
+                .seek(std::io::SeekFrom::Start(0))
+                .map_err(|error| PlayerError::NotAbleToSeek(error.kind()))?;
+
+            // The analyzer only understands MP3, so the tags are the fallback for everything else.
+            let (mut tags, cover_art) = metadata::read_tags(&mut std_file);
+            let (duration, duration_estimated) = match analyzed_duration.or(tags.duration) {
+                Some(duration) => (Some(duration), false),
+                None => {
+                    trace::warning!(
+                        "no duration from the analyzer or the tags",
+                        path = owned_path,
+                        format = format
+                    );
+                    let media = MediaHandle::File(
+                        std_file
+                            .try_clone()
+                            .map_err(|error| PlayerError::UnableToCloneFileHandle(error.kind()))?,
+                    );
+                    Player::fallback_duration(&config, media, format)
+                }
+            };
+            tags.duration = duration;
+            if config.verify_on_load {
+                let verify_file = std_file
+                    .try_clone()
+                    .map_err(|error| PlayerError::UnableToCloneFileHandle(error.kind()))?;
+                Player::verify_media(MediaHandle::File(verify_file), format, duration, config.decoder_backend)?;
+            }
+            std_file
+                .seek(std::io::SeekFrom::Start(0))
+                .map_err(|error| PlayerError::NotAbleToSeek(error.kind()))?;
+
+            Ok((std_file, tags, cover_art, duration_estimated))
+        })
+        .await?;
+        let duration = tags.duration;
+
+        let media = MediaHandle::File(std_file);
+        let loudness = match self.normalization_target {
+            Some(_) => {
+                let analysis_handle = media
+                    .try_clone()
+                    .map_err(|error| PlayerError::UnableToCloneFileHandle(error.kind()))?;
+                let loudness =
+                    Player::measure_loudness(analysis_handle, cache_key.as_ref(), self.config.decoder_backend).await;
+                (&media)
+                    .seek(std::io::SeekFrom::Start(0))
+                    .map_err(|error| PlayerError::NotAbleToSeek(error.kind()))?;
+                loudness
+            }
+            None => None,
+        };
+
+        let track = Track {
+            file_handle: media
+                .try_clone()
+                .map_err(|error| PlayerError::UnableToCloneFileHandle(error.kind()))?,
+            origin: match path {
+                Some(path) => PlayerSource::Path(path.to_string()),
+                None => PlayerSource::File,
+            },
+            decoder_seeks: Player::decoder_seeks(
+                self.config.decoder_backend,
+                seek_index.is_some() || sparse_index.is_some(),
+            ),
+            duration_estimated,
+            seek_index,
+            sparse_index,
+            clock: Arc::new(TrackClock::new(duration)),
+            metadata: tags,
+            cover_art,
+            format: None,
+            playlist_index: None,
+            loudness,
+            gain: Arc::new(AtomicU32::new(
+                Player::normalization_gain(self.normalization_target, loudness).to_bits(),
+            )),
         };
+        let track = match self.skip_silence {
+            true => {
+                let (backend, threshold_db) = (self.config.decoder_backend, self.silence_threshold);
+                Player::blocking(move || {
+                    Player::trim_silence(&track, backend, threshold_db);
+                    track
+                })
+                .await
+            }
+            false => track,
+        };
+
+        Ok(PreparedTrack { track, media })
+    }
+}
 
-        std_file.seek(std::io::SeekFrom::Start(0)).unwrap();
-        let reader = BufReader::new(std_file);
-        let source = Decoder::new(reader).unwrap();
+/// The controls `Music` drives its players through. `Player` is the one that makes sound, and
+/// `MockPlayer` stands in for it where there is no output device.
+pub trait AudioPlayer {
+    fn play(&mut self) -> PlayerResult<()>;
+    fn pause(&mut self);
+    fn stop(&mut self);
+    fn seek(&mut self, time_offset: Duration) -> PlayerResult<()>;
+    fn volume(&self) -> f32;
+    fn set_volume(&mut self, volume: f32);
+    /// Scales the player's volume by the level `Music` mixes it at, its group's volume times the
+    /// master volume.
+    fn set_master_volume(&mut self, volume: f32);
+    fn fade_in(&mut self, duration: Duration) -> PlayerResult<()>;
+    fn fade_out(&mut self, duration: Duration, pause: bool);
+    fn elapsed(&self) -> PlayerResult<Duration>;
+    fn duration(&self) -> Option<Duration>;
+    fn state(&self) -> PlaybackState;
+}
 
-        self.sink.append(source);
+/// The volume a fade running on a background thread ramps.
+struct FadeTarget {
+    sink: Arc<Sink>,
+    faded_volume: Arc<AtomicU32>,
+    master_volume: Arc<AtomicU32>,
+    curve: VolumeCurve,
+}
 
-        Ok(())
+impl FadeTarget {
+    /// Stores `volume` as the faded volume and puts the sink at it, scaled by the master volume as
+    /// it is at that moment, so a master volume change part way through a fade is kept.
+    fn set(&self, volume: f32) {
+        self.faded_volume.store(volume.to_bits(), Ordering::SeqCst);
+        self.sink
+            .set_volume(Player::sink_volume(self.curve, volume, &self.master_volume));
     }
+}
 
-    fn get_std_file_handle(&self) -> PlayerResult<&std::fs::File> {
-        match self.file_handle.as_ref() {
-            Some(std_file_handle) => Ok(std_file_handle),
-            None => return Err(PlayerError::NoFileHandle),
+/// Keeps an output stream open on a thread of its own, until this is dropped.
+///
+/// `OutputStream` wraps a cpal stream, which is neither `Send` nor `Sync` on some platforms, so a
+/// player that owned one couldn't be shared between threads. The stream stays on the thread that
+/// opened it instead, and the player only keeps the sink, which is thread safe everywhere.
+///
+/// The thread also keeps checking that the device is still there, since cpal only reports a
+/// device going away to an error callback that rodio doesn't expose.
+struct OutputThread {
+    /// Dropping this wakes the thread up to close the stream.
+    _close: std::sync::mpsc::Sender<()>,
+    /// Set by the thread once the device is gone.
+    lost: Arc<AtomicBool>,
+}
+
+/// A player manages audio functions for a file. Things like play, pause, resume, seek, volume.
+///
+/// Players are `Send` and `Sync` on every platform, so they can be moved to other threads and
+/// shared behind a lock, like `Music` does. Every method that changes playback takes `&mut self`,
+/// and the output stream lives on a thread of its own, see `OutputThread`. The audio itself gets
+/// decoded on cpal's audio callback thread, which only shares atomics and mutexes with the player.
+pub struct Player {
+    sink: Arc<Sink>,
+    output: OutputThread,
+    config: PlayerConfig,
+    /// The current track followed by the queued ones, as of the last `sync_tracks()`.
+    tracks: VecDeque<Track>,
+    /// Bumped on the audio thread every time a track plays through to the end.
+    finished_tracks: Arc<AtomicUsize>,
+    /// How many of the finished tracks have already been dropped from `tracks`.
+    synced_finished_tracks: usize,
+    repeat_mode: Arc<Mutex<RepeatMode>>,
+    /// The silence played before a repeat or A-B loop starts over, see `set_loop_gap()`.
+    loop_gap: Duration,
+    stopped: bool,
+    /// Where the next `play()` seeks to first, set by `set_start_position()`.
+    start_position: Option<Duration>,
+    on_finished: Arc<Mutex<Option<FinishedCallback>>>,
+    /// Shared with every output thread the player opens, which calls it when its device is gone.
+    on_device_lost: Arc<Mutex<Option<DeviceLostCallback>>>,
+    volume: f32,
+    /// The volume as the output is at right now, as the bits of an `f32`. It only differs from
+    /// `volume` while a fade is ramping it, or after fading out without pausing.
+    faded_volume: Arc<AtomicU32>,
+    /// The level of everything the player belongs to, set by `Music`, as the bits of an `f32`.
+    /// Fades read it on every step, so changing it doesn't have to interrupt them.
+    master_volume: Arc<AtomicU32>,
+    volume_curve: VolumeCurve,
+    muted: bool,
+    fade_generation: Arc<AtomicU64>,
+    /// Bumped whenever the sleep timer is set or cleared, which makes a timer still running give
+    /// up on its next check.
+    sleep_timer_generation: Arc<AtomicU64>,
+    /// How much playing time the sleep timer has left, while one is set. The timer's thread
+    /// updates it on every check, so switching devices can start it again where it was.
+    sleep_timer_left: Arc<Mutex<Option<Duration>>>,
+    now_playing: NowPlaying,
+    equalizer: Arc<SharedEqualizer>,
+    levels: Arc<Levels>,
+    /// The balance as the bits of an `f32`, shared with the sources that apply it.
+    balance: Arc<AtomicU32>,
+    /// The loudness tracks are normalized to, when normalization is on.
+    normalization_target: Option<f32>,
+    /// The sample rate every track is resampled to, see `set_target_sample_rate()`.
+    target_sample_rate: Option<u32>,
+    /// Whether tracks have the silence at their ends trimmed, set by `set_skip_silence()`.
+    skip_silence: bool,
+    /// The peak level in dBFS below which audio counts as silence.
+    silence_threshold: f32,
+    /// Bumped whenever silence skipping or its threshold changes, which makes trims still being
+    /// worked out for the old settings give up instead of applying them.
+    trim_generation: Arc<AtomicU64>,
+    /// Cancelled when the player is dropped or switches devices, which ends every
+    /// `subscribe_progress()` subscription to the old output.
+    progress_cancel: CancellationToken,
+}
+
+// Everything `Music` does relies on this, so it shouldn't build if a field stops being thread safe.
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Player>();
+};
+
+impl Player {
+    pub fn new() -> PlayerResult<Self> {
+        Self::with_config(PlayerConfig::default())
+    }
+
+    /// Starts at `volume` instead of full volume, see `PlayerConfig::initial_volume`.
+    pub fn with_volume(volume: f32) -> PlayerResult<Self> {
+        Self::with_config(PlayerConfig {
+            initial_volume: volume,
+            ..PlayerConfig::default()
+        })
+    }
+
+    /// Fails with `PlayerError::InvalidVolume` if the initial volume is outside `0.0..=1.0`.
+    pub fn with_config(config: PlayerConfig) -> PlayerResult<Self> {
+        Self::open(config, None)
+    }
+
+    /// Plays through the output device called `name`, as listed by `list_output_devices()`,
+    /// instead of the system default.
+    pub fn new_with_device(name: &str) -> PlayerResult<Self> {
+        Self::with_config_and_device(PlayerConfig::default(), name)
+    }
+
+    /// Like `with_config()`, but plays through the output device called `name` instead of the
+    /// system default, like `new_with_device()`.
+    pub fn with_config_and_device(config: PlayerConfig, name: &str) -> PlayerResult<Self> {
+        Self::open(config, Some(name))
+    }
+
+    /// Checks `config` before opening the stream, so a bad value fails without touching the
+    /// output device.
+    fn open(config: PlayerConfig, device: Option<&str>) -> PlayerResult<Self> {
+        if !(0.0..=1.0).contains(&config.initial_volume) {
+            return Err(PlayerError::InvalidVolume);
+        }
+        if config.initial_speed.is_nan() || config.initial_speed <= 0.0 {
+            return Err(PlayerError::InvalidSpeed);
+        }
+        if !(-1.0..=1.0).contains(&config.initial_balance) {
+            return Err(PlayerError::InvalidBalance);
+        }
+
+        let on_device_lost = Arc::new(Mutex::new(None));
+        let (output, sink) = Self::open_output(device, Arc::clone(&on_device_lost))?;
+        Ok(Self::with_output(config, output, sink, on_device_lost))
+    }
+
+    /// The names of the output devices audio can be played through. Devices whose name can't be
+    /// read are left out.
+    pub fn list_output_devices() -> Vec<String> {
+        match rodio::cpal::default_host().output_devices() {
+            Ok(devices) => devices.filter_map(|device| device.name().ok()).collect(),
+            Err(_) => Vec::new(),
         }
     }
 
-    fn get_bytes_offset_for_time(&self, time: Duration) -> PlayerResult<u64> {
-        let seek_index = match self.seek_index.as_ref() {
-            Some(seek_index) => seek_index,
-            None => return Err(PlayerError::NoSeekIndex),
-        };
+    /// Moves playback over to the output device called `name` without starting the track over.
+    /// The current track is decoded again from where it is right now, or from the start if it
+    /// has no seek index, followed by the rest of the queue. Volume, speed, the play/pause state
+    /// and the time left on the sleep timer carry over, while a running fade is cancelled and
+    /// `subscribe_progress()` receivers close, since both belong to the old output.
+    ///
+    /// If the device can't be opened the player carries on with the device it had.
+    pub fn switch_device(&mut self, name: &str) -> PlayerResult<()> {
+        self.switch_output(Some(name))
+    }
 
-        let mut offset = 0;
-        for (frame_time, frame_offset) in seek_index {
-            if frame_time > &time {
-                break;
-            }
-            offset = *frame_offset;
+    /// Moves playback over to the system's default output device after the device it was playing
+    /// through went away, picking up from `elapsed()` like `switch_device()` does. The app would
+    /// usually call this from its `on_device_lost()` callback, or once the user confirms, since
+    /// the new default device may be a speaker the user didn't mean to play through.
+    pub fn recover_device(&mut self) -> PlayerResult<()> {
+        self.switch_output(None)
+    }
+
+    fn switch_output(&mut self, name: Option<&str>) -> PlayerResult<()> {
+        let (output, sink) = Self::open_output(name, Arc::clone(&self.on_device_lost))?;
+        self.sync_tracks();
+        // The sleep timer watches the sink, so it is set again on the new one further down.
+        let sleep_timer_left = *self.sleep_timer_left.lock().unwrap_or_else(PoisonError::into_inner);
+        self.clear_sleep_timer();
+        if sleep_timer_left == Some(Duration::ZERO) {
+            // It was fading out, and the fade starts over on the new device.
+            self.faded_volume.store(self.volume.to_bits(), Ordering::SeqCst);
+        }
+
+        let elapsed = self.elapsed().ok();
+        let playing = !self.sink.empty();
+        if self.sink.is_paused() {
+            sink.pause();
+        }
+        sink.set_speed(self.sink.speed());
+
+        self.cancel_fade();
+        self.progress_cancel.cancel();
+        self.progress_cancel = CancellationToken::new();
+        self.sink.stop();
+        self.sink = Arc::new(sink);
+        self.output = output;
+        self.sink.set_volume(self.output_volume());
+        if let Some(left) = sleep_timer_left {
+            self.set_sleep_timer(left);
+        }
+
+        if !playing || !self.is_file_loaded() {
+            return Ok(());
         }
 
-        Ok(offset)
+        match elapsed.filter(|_| self.is_seekable()) {
+            Some(elapsed) => self.seek(elapsed),
+            None => self.seek_to_bytes_offset(0, Duration::ZERO),
+        }
     }
 
-    fn get_time_for_bytes_offset(&self, offset: u64) -> PlayerResult<Duration> {
-        let seek_index = match self.seek_index.as_ref() {
-            Some(seek_index) => seek_index,
-            None => return Err(PlayerError::NoSeekIndex),
-        };
+    /// Opens a stream on the output device called `name`, or on the default device, on a new
+    /// `OutputThread`. Returns once the stream is open, or failed to open.
+    fn open_output(
+        name: Option<&str>,
+        on_device_lost: Arc<Mutex<Option<DeviceLostCallback>>>,
+    ) -> PlayerResult<(OutputThread, Sink)> {
+        let name = name.map(str::to_string);
+        let (opened_sender, opened) = std::sync::mpsc::channel();
+        let (close, closed) = std::sync::mpsc::channel::<()>();
+        let lost = Arc::new(AtomicBool::new(false));
+        let thread_lost = Arc::clone(&lost);
+
+        std::thread::spawn(move || {
+            // The default device is watched by the name it has now, since the default moving to
+            // another device doesn't move the stream along with it.
+            let device = name.clone().or_else(|| {
+                rodio::cpal::default_host()
+                    .default_output_device()
+                    .and_then(|device| device.name().ok())
+            });
+            let (_stream, sink) = match Self::open_stream(name.as_deref()) {
+                Ok(output) => output,
+                Err(error) => {
+                    let _ = opened_sender.send(Err(error));
+                    return;
+                }
+            };
+
+            if opened_sender.send(Ok(sink)).is_err() {
+                return;
+            }
+
+            // Only returns once the player has dropped its end.
+            loop {
+                match closed.recv_timeout(DEVICE_POLL_INTERVAL) {
+                    Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {}
+                    _ => return,
+                }
 
-        let mut time = Duration::from_secs(0);
-        for (frame_time, frame_offset) in seek_index {
-            if frame_offset > &offset {
-                break;
+                if device.as_deref().is_some_and(|device| !Self::is_device_available(device)) {
+                    trace::warning!("the output device is gone", device = device);
+                    thread_lost.store(true, Ordering::SeqCst);
+                    if let Some(callback) = on_device_lost.lock().unwrap_or_else(PoisonError::into_inner).as_ref() {
+                        callback();
+                    }
+                    let _ = closed.recv();
+                    return;
+                }
             }
-            time = *frame_time;
+        });
+
+        let sink = opened.recv().map_err(|_| PlayerError::NoOutputDevice)??;
+        Ok((OutputThread { _close: close, lost }, sink))
+    }
+
+    /// Whether an output device called `name` is connected. A host that can't list its devices
+    /// counts as having it, so a hiccup doesn't look like the device going away.
+    fn is_device_available(name: &str) -> bool {
+        match rodio::cpal::default_host().output_devices() {
+            Ok(mut devices) => devices.any(|device| device.name().is_ok_and(|device_name| device_name == name)),
+            Err(_) => true,
         }
+    }
 
-        Ok(time)
+    fn open_stream(name: Option<&str>) -> PlayerResult<(OutputStream, Sink)> {
+        let (stream, stream_handle) = match name {
+            Some(name) => {
+                let device = rodio::cpal::default_host()
+                    .output_devices()
+                    .map_err(|_| PlayerError::NoOutputDevice)?
+                    .find(|device| device.name().is_ok_and(|device_name| device_name == name))
+                    .ok_or(PlayerError::NoOutputDevice)?;
+                OutputStream::try_from_device(&device).map_err(|_| PlayerError::NoOutputDevice)?
+            }
+            None => OutputStream::try_default().map_err(|_| PlayerError::NoOutputDevice)?,
+        };
+
+        let sink = Sink::try_new(&stream_handle).map_err(|_| PlayerError::UnableToCreateSink)?;
+        Ok((stream, sink))
     }
 
-    pub fn is_seekable(&self) -> bool {
-        self.is_file_loaded() && self.seek_index.is_some()
+    fn with_output(
+        config: PlayerConfig,
+        output: OutputThread,
+        sink: Sink,
+        on_device_lost: Arc<Mutex<Option<DeviceLostCallback>>>,
+    ) -> Self {
+        // The sink hasn't played anything yet, so it is at the right volume before anything does.
+        let volume = config.initial_volume;
+        sink.set_volume(volume);
+        sink.set_speed(config.initial_speed.clamp(MIN_SPEED, MAX_SPEED));
+        let repeat_mode = config.initial_repeat_mode;
+        let equalizer = config.initial_equalizer.clone();
+        let balance = config.initial_balance;
+        let silence_threshold = config.silence_threshold;
+        Self {
+            sink: Arc::new(sink),
+            output,
+            config,
+            tracks: VecDeque::new(),
+            finished_tracks: Arc::new(AtomicUsize::new(0)),
+            synced_finished_tracks: 0,
+            repeat_mode: Arc::new(Mutex::new(repeat_mode)),
+            loop_gap: Duration::ZERO,
+            stopped: false,
+            start_position: None,
+            on_finished: Arc::new(Mutex::new(None)),
+            on_device_lost,
+            volume,
+            faded_volume: Arc::new(AtomicU32::new(volume.to_bits())),
+            master_volume: Arc::new(AtomicU32::new(1f32.to_bits())),
+            volume_curve: VolumeCurve::Linear,
+            muted: false,
+            fade_generation: Arc::new(AtomicU64::new(0)),
+            trim_generation: Arc::new(AtomicU64::new(0)),
+            progress_cancel: CancellationToken::new(),
+            sleep_timer_generation: Arc::new(AtomicU64::new(0)),
+            sleep_timer_left: Arc::new(Mutex::new(None)),
+            now_playing: Arc::new(Mutex::new(None)),
+            equalizer: Arc::new(SharedEqualizer::new(equalizer)),
+            levels: Arc::new(Levels::default()),
+            balance: Arc::new(AtomicU32::new(balance.to_bits())),
+            normalization_target: None,
+            target_sample_rate: None,
+            skip_silence: false,
+            silence_threshold,
+        }
     }
 
-    pub fn seek(&mut self, time_offset: Duration) -> PlayerResult<()> {
-        let bytes_offset = self.get_bytes_offset_for_time(time_offset)?;
-        let mut std_file_handle = self.get_std_file_handle()?;
+    /// The config this player was created with, updated to the volume, repeat mode, speed,
+    /// balance and equalizer it has now, so `with_config()` can create a second player that
+    /// sounds the same, e.g. to crossfade into. The loaded track and the output device aren't
+    /// part of it, since neither can be shared between players.
+    pub fn clone_settings(&self) -> PlayerConfig {
+        PlayerConfig {
+            initial_volume: self.volume.clamp(0.0, 1.0),
+            initial_repeat_mode: self.repeat_mode(),
+            initial_speed: self.speed(),
+            initial_balance: self.balance(),
+            initial_equalizer: self.equalizer(),
+            silence_threshold: self.silence_threshold,
+            ..self.config.clone()
+        }
+    }
 
-        std_file_handle
-            .seek(std::io::SeekFrom::Start(bytes_offset))
-            .map_err(|_| PlayerError::NotAbleToSeek)?;
+    pub fn is_file_loaded(&self) -> bool {
+        self.current_track().is_some()
+    }
+
+    /// The track that is playing right now. Tracks that finished since the last `sync_tracks()`
+    /// are skipped over, and once everything has finished the last track stays current.
+    fn current_track(&self) -> Option<&Track> {
+        let finished = self.finished_tracks.load(Ordering::SeqCst) - self.synced_finished_tracks;
+        self.tracks.get(finished.min(self.tracks.len().saturating_sub(1)))
+    }
+
+    /// Drops the tracks that have finished playing from the front of `tracks`.
+    fn sync_tracks(&mut self) {
+        let finished = self.finished_tracks.load(Ordering::SeqCst) - self.synced_finished_tracks;
+        let finished = finished.min(self.tracks.len().saturating_sub(1));
+
+        self.tracks.drain(..finished);
+        self.synced_finished_tracks += finished;
+    }
+
+    /// Fails with `PlayerError::DeviceLost` once the output device has gone away. Clearing the
+    /// sink, or appending to it after a stop, waits for the device to play out what is queued,
+    /// which a lost device never does, so everything that does either checks this first.
+    fn ensure_device(&self) -> PlayerResult<()> {
+        match self.output.lost.load(Ordering::SeqCst) {
+            true => Err(PlayerError::DeviceLost),
+            false => Ok(()),
+        }
+    }
+
+    pub async fn load_path(&mut self, path: &str) -> PlayerResult<()> {
+        self.ensure_device()?;
+        let file = match tokio::fs::File::open(path).await {
+            Ok(file) => file,
+            Err(error) => return Err(PlayerError::unable_to_open(path, error)),
+        };
+
+        self.load_track(file, Some(path)).await
+    }
+
+    /// Loads a file like `load_path()`, but gives up with `PlayerError::LoadCancelled` as soon as
+    /// `cancel` is cancelled, e.g. when the user picks another track while a large file is still
+    /// being analyzed. Nothing is replaced until the file is ready to play, so a cancelled or
+    /// failed load leaves the player as it was, still playing whatever it had.
+    ///
+    /// Cancelling takes effect the next time loading waits on the file, which is often while the
+    /// seek index gets built.
+    pub async fn load_path_cancellable(&mut self, path: &str, cancel: CancellationToken) -> PlayerResult<()> {
+        self.ensure_device()?;
+        let open = std::pin::pin!(tokio::fs::File::open(path));
+        let file = match Self::until_cancelled(open, &cancel).await? {
+            Ok(file) => file,
+            Err(error) => return Err(PlayerError::unable_to_open(path, error)),
+        };
+
+        let (mut track, media) = {
+            let prepare = std::pin::pin!(self.prepare_track(file, Some(path)));
+            Self::until_cancelled(prepare, &cancel).await??
+        };
+
+        self.unload();
+        track.format = Some(self.append_source(media, Duration::ZERO, &track)?);
+        self.tracks.push_back(track);
+        self.stopped = false;
 
         Ok(())
     }
 
-    pub fn play(&mut self) {
-        self.sink.play();
+    /// Runs `future` to completion unless `cancel` is cancelled first.
+    async fn until_cancelled<F: Future>(mut future: Pin<&mut F>, cancel: &CancellationToken) -> PlayerResult<F::Output> {
+        let mut cancelled = std::pin::pin!(cancel.cancelled());
+        std::future::poll_fn(|cx| {
+            if cancelled.as_mut().poll(cx).is_ready() {
+                trace::debug!("load cancelled");
+                return Poll::Ready(Err(PlayerError::LoadCancelled));
+            }
+            future.as_mut().poll(cx).map(Ok)
+        })
+        .await
     }
 
-    pub fn pause(&mut self) {
-        self.sink.pause();
+    /// Loads a file like `load_path()`, calling `on_progress` with how far loading has got, from
+    /// `0.0` to `1.0`, so an import can show a real progress bar. Most of the time goes into the
+    /// analyzer scanning the file for its duration and seek index, so progress is how far into
+    /// the file it has read, and it only ever goes up. `1.0` is reported once the track is
+    /// loaded, which is immediate for files with a cached seek index. Like
+    /// `load_path_cancellable()`, whatever was playing carries on until the new file is ready.
+    pub async fn load_path_with_progress(&mut self, path: &str, on_progress: impl Fn(f32)) -> PlayerResult<()> {
+        self.ensure_device()?;
+        let file = match tokio::fs::File::open(path).await {
+            Ok(file) => file,
+            Err(error) => return Err(PlayerError::unable_to_open(path, error)),
+        };
+        let len = file
+            .metadata()
+            .await
+            .map_err(|error| PlayerError::UnableToReadMetadata(error.kind()))?
+            .len();
+        // A cloned handle shares the file position with the ones the analyzer reads through.
+        let position = file
+            .try_clone()
+            .await
+            .map_err(|error| PlayerError::UnableToCloneFileHandle(error.kind()))?
+            .into_std()
+            .await;
+
+        on_progress(0.0);
+        let (mut track, media) = {
+            let prepare = std::pin::pin!(self.prepare_track(file, Some(path)));
+            Self::with_progress(prepare, position, len, &on_progress).await?
+        };
+
+        self.unload();
+        track.format = Some(self.append_source(media, Duration::ZERO, &track)?);
+        self.tracks.push_back(track);
+        self.stopped = false;
+        on_progress(1.0);
+
+        Ok(())
     }
 
-    pub fn stop(&mut self) {
-        self.duration = None;
-        self.seek_index = None;
-        self.file_handle = None;
-        self.sink.stop();
+    /// Runs `future` to completion, reporting the position of `file` as a fraction of `len`
+    /// every time it moves further in between polls.
+    async fn with_progress<F: Future>(
+        mut future: Pin<&mut F>,
+        mut file: std::fs::File,
+        len: u64,
+        on_progress: &impl Fn(f32),
+    ) -> F::Output {
+        let mut reported = 0.0;
+        std::future::poll_fn(|cx| {
+            let output = future.as_mut().poll(cx);
+            if output.is_pending() && len > 0 {
+                if let Ok(position) = file.stream_position() {
+                    // Short of 1.0, which is left for when loading has finished.
+                    let progress = (position as f32 / len as f32).min(0.99);
+                    if progress > reported {
+                        reported = progress;
+                        on_progress(progress);
+                    }
+                }
+            }
+            output
+        })
+        .await
     }
 
-    pub fn elapsed(&self) -> PlayerResult<Duration> {
-        let mut file_handle = self.get_std_file_handle()?;
-        let cursor_position = file_handle
-            .seek(std::io::SeekFrom::Current(0))
-            .map_err(|_| PlayerError::NotAbleToSeek)?;
+    /// Loads a file like `load_path()`, but leaves the player paused at the start of it. All the
+    /// work of loading, like analyzing the file, building its seek index, reading its tags and
+    /// setting up the decoder, is done by the time this returns, so `play()` starts it straight
+    /// away. If the file can't be loaded the player is left in its previous play/pause state.
+    pub async fn prefetch(&mut self, path: &str) -> PlayerResult<()> {
+        let paused = self.sink.is_paused();
+        self.sink.pause();
+
+        let result = self.load_path(path).await;
+        if result.is_err() && !paused {
+            self.sink.play();
+        }
 
-        let elapsed_time = self.get_time_for_bytes_offset(cursor_position)?;
-        Ok(elapsed_time)
+        result
     }
 
-    pub fn duration(&self) -> Option<Duration> {
-        self.duration
+    /// The path of the current track, if it was loaded with `load_path()` or `enqueue_path()`.
+    pub fn path(&self) -> Option<&str> {
+        match self.source() {
+            Some(PlayerSource::Path(path)) => Some(path),
+            _ => None,
+        }
     }
 
-    pub fn volume(&self) -> f32 {
-        self.sink.volume()
+    /// Where the current track was loaded from.
+    pub fn source(&self) -> Option<&PlayerSource> {
+        self.current_track().map(|track| &track.origin)
     }
 
-    pub fn set_volume(&mut self, volume: f32) {
-        self.sink.set_volume(volume);
+    pub async fn load_file(&mut self, file: tokio::fs::File) -> PlayerResult<()> {
+        self.load_track(file, None).await
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self, file), err(Debug)))]
+    async fn load_track(&mut self, file: tokio::fs::File, path: Option<&str>) -> PlayerResult<()> {
+        self.ensure_device()?;
+        self.unload();
+
+        let started = Instant::now();
+        let (mut track, media) = self.prepare_track(file, path).await?;
+
+        // Only take over the new file once it decodes, so a bad file leaves the player empty.
+        track.format = Some(self.append_source(media, Duration::ZERO, &track)?);
+        trace::debug!(
+            "loaded track",
+            load_time = started.elapsed(),
+            duration = track.metadata.duration,
+            seekable = track.seek_index.is_some() || track.sparse_index.is_some(),
+        );
+        self.tracks.push_back(track);
+        self.stopped = false;
+
+        Ok(())
+    }
+
+    /// Queues another file to play straight after the ones already loaded, without interrupting
+    /// the current track. If nothing is playing it starts right away.
+    ///
+    /// `duration()`, `elapsed()` and `seek()` always refer to the track that is playing at the
+    /// time, so they switch over as soon as the sink moves on to the next track.
+    pub async fn enqueue_path(&mut self, path: &str) -> PlayerResult<()> {
+        self.ensure_device()?;
+        let file = match tokio::fs::File::open(path).await {
+            Ok(file) => file,
+            Err(error) => return Err(PlayerError::unable_to_open(path, error)),
+        };
+
+        let (mut track, media) = self.prepare_track(file, Some(path)).await?;
+
+        // A stopped track isn't in the sink any more, so the new one takes its place.
+        if self.stopped {
+            self.unload();
+        }
+        self.sync_tracks();
+        track.format = Some(self.append_source(media, Duration::ZERO, &track)?);
+        self.tracks.push_back(track);
+        self.stopped = false;
+
+        Ok(())
+    }
+
+    /// Queues `duration` of silence after the tracks already loaded, e.g. for a pause between
+    /// flashcard prompts. If nothing is playing it starts right away. It is played at the sample
+    /// rate of the last queued track, or the target sample rate if one is set.
+    ///
+    /// The gap is queued as a track of its own, so while it plays `elapsed()` and `duration()`
+    /// report how far into the gap playback is and how long the gap is, `skip()` moves past it and
+    /// `queue_len()` counts it. It has no file behind it, so `path()` is `None`, it can't be
+    /// seeked and `reload()` fails with `PlayerError::NoFileHandle`.
+    pub fn append_silence(&mut self, duration: Duration) {
+        let format = self
+            .tracks
+            .back()
+            .and_then(|track| track.format)
+            .unwrap_or(DEFAULT_SILENCE_FORMAT);
+        let format = AudioFormat {
+            sample_rate: self.target_sample_rate.unwrap_or(format.sample_rate),
+            ..format
+        };
+        let track = Track {
+            file_handle: MediaHandle::from_bytes(Vec::new()),
+            origin: PlayerSource::Silence,
+            seek_index: None,
+            sparse_index: None,
+            decoder_seeks: false,
+            duration_estimated: false,
+            clock: Arc::new(TrackClock::new(Some(duration))),
+            metadata: TrackMetadata {
+                duration: Some(duration),
+                ..TrackMetadata::default()
+            },
+            cover_art: None,
+            format: Some(format),
+            playlist_index: None,
+            loudness: None,
+            gain: Arc::new(AtomicU32::new(1f32.to_bits())),
+        };
+
+        // A stopped track isn't in the sink any more, so the gap takes its place.
+        if self.stopped {
+            self.unload();
+        }
+        self.sync_tracks();
+        self.append_silent(Duration::ZERO, &track);
+        self.tracks.push_back(track);
+        self.stopped = false;
+    }
+
+    /// Replaces whatever is loaded with the tracks of `playlist`. Every track is opened and queued
+    /// on the sink up front, so they play back to back without any silence in between. A track
+    /// that can't be opened or decoded is skipped with a warning, and only if none of them can be
+    /// is `PlayerError::EmptyPlaylist` returned.
+    pub async fn play_playlist(&mut self, playlist: Playlist) -> PlayerResult<()> {
+        self.ensure_device()?;
+        self.unload();
+
+        for (index, path) in playlist.paths.iter().enumerate() {
+            if let Err(error) = self.enqueue_path(path).await {
+                trace::warning!(
+                    "skipping a playlist track that couldn't be loaded",
+                    index = index,
+                    path = path,
+                    error = error
+                );
+                continue;
+            }
+
+            if let Some(track) = self.tracks.back_mut() {
+                track.playlist_index = Some(index);
+            }
+        }
+
+        match self.tracks.is_empty() {
+            true => Err(PlayerError::EmptyPlaylist),
+            false => Ok(()),
+        }
+    }
+
+    /// The position in the playlist of the track that is playing, if it came from
+    /// `play_playlist()`. It moves on as each track finishes, the same way `path()` does.
+    pub fn playlist_index(&self) -> Option<usize> {
+        self.current_track().and_then(|track| track.playlist_index)
+    }
+
+    /// Drops the current track and moves on to the next queued one. Skipping the last track leaves
+    /// the player `Finished`, as if it had played through, but doesn't fire the finished callback.
+    pub fn skip(&mut self) {
+        self.sync_tracks();
+        if self.queue_len() == 0 {
+            return;
+        }
+
+        // Every track sits in the sink as one source carrying its finished signal, which skipping
+        // drops, so the track is counted as finished here instead.
+        self.sink.skip_one();
+        self.finished_tracks.fetch_add(1, Ordering::SeqCst);
+        self.sync_tracks();
+    }
+
+    /// The number of tracks that haven't finished playing yet, counting the current one.
+    pub fn queue_len(&self) -> usize {
+        let finished = self.finished_tracks.load(Ordering::SeqCst) - self.synced_finished_tracks;
+        self.tracks.len().saturating_sub(finished)
+    }
+
+    /// Analyzes a file and opens it for decoding, without touching the sink or the loaded tracks.
+    /// Files opened by path can use the on-disk seek index cache.
+    async fn prepare_track(&self, file: tokio::fs::File, path: Option<&str>) -> PlayerResult<(Track, MediaHandle)> {
+        let prepared = self.track_loader().prepare(file, path).await?;
+        Ok((prepared.track, prepared.media))
+    }
+
+    /// Takes a snapshot of the settings loading a track depends on, to get files ready for this
+    /// player with `TrackLoader::prepare_path()` without holding on to it, e.g. while it is
+    /// locked.
+    pub fn track_loader(&self) -> TrackLoader {
+        TrackLoader {
+            config: self.config.clone(),
+            normalization_target: self.normalization_target,
+            skip_silence: self.skip_silence,
+            silence_threshold: self.silence_threshold,
+        }
+    }
+
+    /// Loads a track got ready by `TrackLoader::prepare_path()`, replacing whatever was loaded like
+    /// `load_path()` does. The file has already been analyzed, so this only sets up the decoder
+    /// and is quick enough to call with the player locked. The loader should come from this
+    /// player's `track_loader()`, or one with the same config, since the track was prepared with
+    /// its settings.
+    pub fn load_prepared(&mut self, prepared: PreparedTrack) -> PlayerResult<()> {
+        self.ensure_device()?;
+        self.unload();
+
+        let PreparedTrack { mut track, media } = prepared;
+        track.format = Some(self.append_source(media, Duration::ZERO, &track)?);
+        self.tracks.push_back(track);
+        self.stopped = false;
+
+        Ok(())
+    }
+
+    /// Checks that the file at `path` has the extension of one of the `SUPPORTED_FORMATS` and
+    /// starts the way that format does, without decoding it. Mislabelled files are rejected even
+    /// if the decoder could play them, and so are files that can't be opened.
+    pub fn is_supported_format(path: &str) -> bool {
+        let extension = Self::extension_of(Some(path)).to_lowercase();
+        if !SUPPORTED_FORMATS.contains(&extension.as_str()) {
+            return false;
+        }
+
+        match std::fs::File::open(path).map(media::sniff_reader) {
+            Ok(Ok(format)) => format == Some(extension.as_str()),
+            _ => false,
+        }
+    }
+
+    /// Reads what a library scan needs to know about the file at `path`, like its duration, tags
+    /// and sample format, without a player or an output stream. Only the tags and the start of
+    /// the audio are read, so this is much quicker than loading the file, although it is
+    /// blocking. The seek index isn't built, so a duration missing from the tags and the header
+    /// is estimated from the bitrate for MP3s and left unknown for anything else.
+    ///
+    /// Fails like loading would for files that can't be opened, aren't in a supported format or
+    /// can't be decoded.
+    pub fn probe(path: &str) -> PlayerResult<TrackProbe> {
+        let config = PlayerConfig::default();
+        let mut file = std::fs::File::open(path).map_err(|error| PlayerError::unable_to_open(path, error))?;
+        let format = media::sniff_reader(&mut file)
+            .map_err(|error| PlayerError::NotAbleToSeek(error.kind()))?
+            .filter(|format| config.decoder_backend.formats().contains(format))
+            .ok_or_else(|| PlayerError::UnsupportedFormat {
+                extension: Self::extension_of(Some(path)),
+                supported: config.decoder_backend.formats(),
+            })?;
+
+        let (mut metadata, _) = metadata::read_tags(&mut file);
+        file.seek(std::io::SeekFrom::Start(0))
+            .map_err(|error| PlayerError::NotAbleToSeek(error.kind()))?;
+        let media = MediaHandle::File(file);
+        let decoder = AudioDecoder::new(
+            media
+                .try_clone()
+                .map_err(|error| PlayerError::UnableToCloneFileHandle(error.kind()))?,
+            Self::analysis_settings(config.decoder_backend),
+        )?;
+        let audio_format = AudioFormat::of(&decoder);
+
+        let (duration, duration_estimated) = match metadata.duration {
+            Some(duration) => (Some(duration), false),
+            None => Self::fallback_duration(&config, media, Some(format)),
+        };
+        metadata.duration = duration;
+
+        Ok(TrackProbe {
+            format,
+            audio_format,
+            duration,
+            duration_estimated,
+            metadata,
+            seekable: format == "mp3" || config.decoder_backend.can_seek(),
+        })
+    }
+
+    fn extension_of(path: Option<&str>) -> String {
+        path.and_then(|path| std::path::Path::new(path).extension())
+            .map(|extension| extension.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+
+    fn unsupported_format(backend: DecoderBackend, path: Option<&str>) -> PlayerError {
+        PlayerError::UnsupportedFormat {
+            extension: Self::extension_of(path),
+            supported: backend.formats(),
+        }
+    }
+
+    fn decoder_seeks(backend: DecoderBackend, indexed: bool) -> bool {
+        !indexed && backend.can_seek()
+    }
+
+    fn decoder_settings(&self, track: &Track) -> DecoderSettings {
+        DecoderSettings {
+            backend: self.config.decoder_backend,
+            read_buffer_size: self.config.read_buffer_size,
+            seeks: track.decoder_seeks,
+        }
+    }
+
+    /// The settings for decoding a whole file once from the start, e.g. to analyze it.
+    fn analysis_settings(backend: DecoderBackend) -> DecoderSettings {
+        DecoderSettings {
+            backend,
+            read_buffer_size: DEFAULT_READ_BUFFER_SIZE,
+            seeks: false,
+        }
+    }
+
+    /// Works out the duration of a file that neither the analyzer nor the tags know it for, along
+    /// with whether it is only an estimate. The decoder may read it from the file's header, an
+    /// MP3 gets estimated from its bitrate, and as a last resort `decode_for_duration` counts the
+    /// samples.
+    fn fallback_duration(config: &PlayerConfig, media: MediaHandle, format: Option<&str>) -> (Option<Duration>, bool) {
+        let backend = config.decoder_backend;
+        let rewound = |media: &MediaHandle| {
+            let mut media = media.try_clone().ok()?;
+            media.seek(std::io::SeekFrom::Start(0)).ok()?;
+            Some(media)
+        };
+
+        let header_duration = rewound(&media)
+            .and_then(|media| AudioDecoder::new(media, Self::analysis_settings(backend)).ok())
+            .and_then(|decoder| decoder.total_duration());
+        if header_duration.is_some() {
+            return (header_duration, false);
+        }
+
+        if format == Some("mp3") {
+            if let Some(estimate) = rewound(&media).and_then(|media| mp3::estimate_duration(media).ok().flatten()) {
+                trace::debug!("estimated the duration from the bitrate", duration = estimate);
+                return (Some(estimate), true);
+            }
+        }
+
+        if !config.decode_for_duration {
+            return (None, false);
+        }
+        let decoded = rewound(&media)
+            .and_then(|media| AudioDecoder::new(media, Self::analysis_settings(backend)).ok())
+            .map(|decoder| {
+                let sample_rate = decoder.sample_rate();
+                let channels = u64::from(decoder.channels().max(1));
+                Self::sample_time(decoder.count() as u64 / channels, sample_rate)
+            });
+        (decoded, false)
+    }
+
+    /// Checks that `media` decodes all the way through, for `PlayerConfig::verify_on_load`. MP3s
+    /// have their frame headers checked for one that runs past the end of the file, which finds
+    /// where a truncated file was cut off. Every format is then decoded, and decoding that stops
+    /// well short of the known `duration` counts as corrupt at the byte the decoder had read up
+    /// to, which is only as precise as its read buffer.
+    fn verify_media(
+        media: MediaHandle,
+        format: Option<&str>,
+        duration: Option<Duration>,
+        backend: DecoderBackend,
+    ) -> PlayerResult<()> {
+        if format == Some("mp3") {
+            let scan_handle = media
+                .try_clone()
+                .map_err(|error| PlayerError::UnableToCloneFileHandle(error.kind()))?;
+            if let Some(at_byte) =
+                mp3::truncated_at(scan_handle).map_err(|error| PlayerError::NotAbleToSeek(error.kind()))?
+            {
+                trace::warning!("the file is truncated", at_byte = at_byte);
+                return Err(PlayerError::CorruptFile { at_byte });
+            }
+        }
+
+        let mut position_handle = media
+            .try_clone()
+            .map_err(|error| PlayerError::UnableToCloneFileHandle(error.kind()))?;
+        position_handle
+            .seek(std::io::SeekFrom::Start(0))
+            .map_err(|error| PlayerError::NotAbleToSeek(error.kind()))?;
+        let decoder = AudioDecoder::new(media, Self::analysis_settings(backend))
+            .map_err(|_| PlayerError::CorruptFile { at_byte: 0 })?;
+        let sample_rate = decoder.sample_rate();
+        let channels = u64::from(decoder.channels().max(1));
+        let samples = decoder.count() as u64;
+
+        let decoded = Self::sample_time(samples / channels, sample_rate);
+        if duration.is_some_and(|duration| decoded.saturating_add(VERIFY_DURATION_TOLERANCE) < duration) {
+            let at_byte = position_handle
+                .stream_position()
+                .map_err(|error| PlayerError::NotAbleToSeek(error.kind()))?;
+            trace::warning!(
+                "the file stopped decoding early",
+                at_byte = at_byte,
+                decoded = decoded,
+                duration = duration
+            );
+            return Err(PlayerError::CorruptFile { at_byte });
+        }
+
+        Ok(())
+    }
+
+    /// Reads the seek index from the cache when there is an entry for this version of the file,
+    /// and otherwise builds it with the analyzer and caches the result.
+    async fn build_seek_index(
+        analyzer: &mut vpr_audio_analyzer::Analyzer,
+        cache_key: Option<&CacheKey>,
+    ) -> Option<Vec<(Duration, u64)>> {
+        if let Some(cache_key) = cache_key {
+            if let Some(seek_index) = cache::read(cache::SEEK_INDEX, cache_key).await {
+                return Some(seek_index);
+            }
+        }
+
+        let seek_index = match analyzer.get_seek_index().await {
+            Ok(seek_index) => seek_index,
+            Err(error) => {
+                trace::warning!("couldn't build a seek index", error = error);
+                return None;
+            }
+        };
+        if let Some(cache_key) = cache_key {
+            cache::write(cache::SEEK_INDEX, cache_key, &seek_index).await;
+        }
+
+        Some(seek_index)
+    }
+
+    /// Drops entries so that consecutive ones are at least `granularity` apart. The cache keeps
+    /// the full index, so changing the granularity doesn't need it to be rebuilt.
+    fn thin_seek_index(seek_index: Vec<(Duration, u64)>, granularity: Duration) -> Vec<(Duration, u64)> {
+        let mut next_time = Duration::ZERO;
+        seek_index
+            .into_iter()
+            .filter(|(frame_time, _)| {
+                if *frame_time < next_time {
+                    return false;
+                }
+                next_time = frame_time.saturating_add(granularity);
+                true
+            })
+            .collect()
+    }
+
+    /// Reads the loudness of a file from the cache when there is an entry for this version of it,
+    /// and otherwise measures it and caches the result.
+    async fn measure_loudness(media: MediaHandle, cache_key: Option<&CacheKey>, backend: DecoderBackend) -> Option<f32> {
+        if let Some(cache_key) = cache_key {
+            if let Some(loudness) = cache::read(cache::LOUDNESS, cache_key).await {
+                return Some(loudness);
+            }
+        }
+
+        let loudness = Self::blocking(move || Self::rms_loudness(media, backend)).await?;
+        if let Some(cache_key) = cache_key {
+            cache::write(cache::LOUDNESS, cache_key, &loudness).await;
+        }
+
+        Some(loudness)
+    }
+
+    /// Runs `work` on tokio's blocking thread pool, so reading and decoding whole files doesn't
+    /// hold up the runtime. A panic in `work` carries on in the caller, as if it had run there.
+    pub(crate) async fn blocking<T: Send + 'static>(work: impl FnOnce() -> T + Send + 'static) -> T {
+        match tokio::task::spawn_blocking(work).await {
+            Ok(output) => output,
+            Err(error) => std::panic::resume_unwind(error.into_panic()),
+        }
+    }
+
+    /// Decodes the whole track and returns its RMS level in dBFS, a rough stand-in for integrated
+    /// loudness since the analyzer can't measure that. `None` for silent or undecodable audio.
+    fn rms_loudness(media: MediaHandle, backend: DecoderBackend) -> Option<f32> {
+        let decoder = AudioDecoder::new(media, Self::analysis_settings(backend)).ok()?;
+
+        let mut sum_of_squares = 0.0f64;
+        let mut samples = 0u64;
+        for sample in decoder {
+            let sample = f64::from(sample) / f64::from(i16::MAX);
+            sum_of_squares += sample * sample;
+            samples += 1;
+        }
+
+        let rms = (sum_of_squares / samples.max(1) as f64).sqrt();
+        match rms > 0.0 {
+            true => Some(20.0 * rms.log10() as f32),
+            false => None,
+        }
+    }
+
+    /// Deletes every cached seek index, forcing them to be rebuilt the next time a file is loaded.
+    pub async fn clear_seek_cache() -> PlayerResult<()> {
+        cache::clear(cache::SEEK_INDEX)
+            .await
+            .map_err(|_| PlayerError::UnableToClearCache)
+    }
+
+    /// Loads audio that is already in memory, e.g. downloaded or decrypted, without going through
+    /// a temporary file. The analyzer can only read from files, so the duration comes from the
+    /// decoder or the tags instead and no seek index is built, which means in-memory audio can
+    /// only be seeked with `DecoderBackend::Symphonia`.
+    pub async fn load_bytes(&mut self, data: Vec<u8>) -> PlayerResult<()> {
+        self.load_memory(data, PlayerSource::Bytes).await
+    }
+
+    async fn load_memory(&mut self, data: Vec<u8>, origin: PlayerSource) -> PlayerResult<()> {
+        self.ensure_device()?;
+        self.unload();
+
+        let loader = self.track_loader();
+        let (track, source, repeat_file_handle) = Self::blocking(move || loader.prepare_memory(data, origin)).await?;
+
+        self.append_decoder(source, repeat_file_handle, Duration::ZERO, &track);
+        self.tracks.push_back(track);
+        self.stopped = false;
+
+        Ok(())
+    }
+
+    /// Downloads a remote audio file over HTTP(S) and plays it from memory. The whole response is
+    /// buffered before playback starts and then handed to `load_bytes()`, so the same limits apply:
+    /// downloaded audio has no seek index.
+    pub async fn load_url(&mut self, url: &str) -> PlayerResult<()> {
+        self.ensure_device()?;
+        let response = reqwest::get(url)
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|err| PlayerError::NetworkError(err.to_string()))?;
+
+        let data = response
+            .bytes()
+            .await
+            .map_err(|err| PlayerError::NetworkError(err.to_string()))?;
+
+        self.load_memory(data.to_vec(), PlayerSource::Url(url.to_string())).await
+    }
+
+    /// Loads a file bundled with the app, given its path relative to the resource directory, the
+    /// same way as `load_path()`.
+    #[cfg(feature = "tauri")]
+    pub async fn load_resource(&mut self, app_handle: &tauri::AppHandle, resource: &str) -> PlayerResult<()> {
+        use tauri::{path::BaseDirectory, Manager};
+
+        let path = app_handle
+            .path()
+            .resolve(resource, BaseDirectory::Resource)
+            .map_err(|_| PlayerError::UnableToResolveResource)?;
+        let path = path.to_str().ok_or(PlayerError::UnableToResolveResource)?;
+
+        self.load_path(path).await
+    }
+
+    /// Loads the current track again from wherever it came from: files loaded by path are opened
+    /// again, so changes on disk are picked up, URLs are downloaded again, and handles or bytes
+    /// passed in by the caller are decoded again from the start. Like the other loaders this
+    /// replaces the queue, while volume, mute, speed and the repeat mode carry over.
+    pub async fn reload(&mut self) -> PlayerResult<()> {
+        let track = match self.current_track() {
+            Some(track) => track,
+            None => return Err(PlayerError::NoFileHandle),
+        };
+
+        match &track.origin {
+            PlayerSource::Path(path) => {
+                let path = path.clone();
+                self.load_path(&path).await
+            }
+            PlayerSource::Url(url) => {
+                let url = url.clone();
+                self.load_url(&url).await
+            }
+            PlayerSource::File | PlayerSource::Bytes => match &track.file_handle {
+                MediaHandle::File(file) => {
+                    let mut file = file
+                        .try_clone()
+                        .map_err(|error| PlayerError::UnableToCloneFileHandle(error.kind()))?;
+                    file.seek(std::io::SeekFrom::Start(0))
+                        .map_err(|error| PlayerError::NotAbleToSeek(error.kind()))?;
+                    self.load_file(tokio::fs::File::from_std(file)).await
+                }
+                MediaHandle::Memory { data, .. } => {
+                    let data = data.to_vec();
+                    self.load_bytes(data).await
+                }
+            },
+            PlayerSource::Silence => Err(PlayerError::NoFileHandle),
+        }
+    }
+
+    /// Swaps the current track for the file at `path` without stopping, e.g. to switch from a
+    /// low bitrate preview to the full file. Volume, speed, repeat mode and the play/pause state
+    /// stay as they are, as does the rest of the queue, and with `preserve_position` the new file
+    /// picks up from where the old one was, clamped to its duration. Restoring the position needs
+    /// the new file to be seekable, otherwise it starts from the beginning.
+    ///
+    /// The new file is loaded before the old one is let go of, so if it can't be loaded the
+    /// current track carries on as if nothing happened. With nothing loaded this is the same as
+    /// `load_path()`.
+    pub async fn replace_source(&mut self, path: &str, preserve_position: bool) -> PlayerResult<()> {
+        self.ensure_device()?;
+        if !self.is_file_loaded() {
+            return self.load_path(path).await;
+        }
+
+        let file = match tokio::fs::File::open(path).await {
+            Ok(file) => file,
+            Err(error) => return Err(PlayerError::unable_to_open(path, error)),
+        };
+        let (mut track, media) = self.prepare_track(file, Some(path)).await?;
+
+        self.sync_tracks();
+        // The new file is trimmed on its own, so the position carries over untrimmed.
+        let position = match preserve_position && !self.stopped {
+            true => self.current_track().map(|current| current.clock.track_position()),
+            false => None,
+        };
+        let position = match track.metadata.duration {
+            Some(duration) => position.map(|position| position.min(duration)),
+            None => position,
+        };
+        let paused = self.sink.is_paused();
+        let stopped = self.stopped;
+        track.playlist_index = self.tracks.front().and_then(|current| current.playlist_index);
+        let queue: Vec<Track> = self.tracks.drain(..).skip(1).collect();
+
+        // Stay paused while the sink is rebuilt, so neither the start of the new file nor the
+        // end of the old one is heard in between.
+        self.sink.pause();
+        self.unload();
+        track.format = Some(self.append_source(media, Duration::ZERO, &track)?);
+        self.tracks.push_back(track);
+        self.tracks.extend(queue);
+
+        // Seeking rebuilds the sink from the new track on, which queues everything after it again.
+        let result = match position.filter(|_| self.is_seekable()) {
+            Some(position) => self.seek_track_time(position),
+            None => self.seek_to_bytes_offset(0, Duration::ZERO),
+        };
+        if stopped {
+            self.stop();
+        } else if !paused {
+            self.sink.play();
+        }
+
+        result
+    }
+
+    /// Takes over the current track of `from`, e.g. to carry on playing it on another output
+    /// device. The file, seek index, tags and measured loudness are reused instead of being loaded
+    /// again, and playback carries on from the same position and in the same play/pause state.
+    /// Restoring the position is best effort, since tracks without a seek index start over.
+    ///
+    /// `from` is unloaded, and its queue is not carried over. This player's own queue is replaced,
+    /// like with the other loaders.
+    pub fn take_track_from(&mut self, from: &mut Player) -> PlayerResult<()> {
+        self.ensure_device()?;
+        let handed_over = from.hand_over_track()?;
+        self.take_over_track(handed_over)
+    }
+
+    /// Takes the current track out of this player for `take_over_track()` on another one, along
+    /// with where it was and whether it was paused, and unloads this player. This is the first
+    /// half of `take_track_from()`, for players that can't both be borrowed at once, like ones
+    /// on threads of their own.
+    pub fn hand_over_track(&mut self) -> PlayerResult<HandedOverTrack> {
+        let elapsed = self.elapsed().ok();
+        let paused = self.sink.is_paused();
+        let track = self.current_track().ok_or(PlayerError::NoFileHandle)?;
+
+        // Files loaded from a path get a cursor of their own, but a file handle passed in by the
+        // caller can only be shared, which is fine once this player has stopped reading from it.
+        let file_handle = match (&track.origin, track.file_handle.independent_clone()) {
+            (_, Some(media)) => media,
+            (PlayerSource::Path(path), None) => {
+                MediaHandle::File(std::fs::File::open(path).map_err(|error| PlayerError::unable_to_open(path, error))?)
+            }
+            (_, None) => track
+                .file_handle
+                .try_clone()
+                .map_err(|error| PlayerError::UnableToCloneFileHandle(error.kind()))?,
+        };
+
+        let clock = Arc::new(TrackClock::new(track.clock.track_duration()));
+        clock.copy_trim(&track.clock);
+        // How the track is decoded and how loud it is played depend on the player taking it over,
+        // which sets them again.
+        let track = Track {
+            file_handle,
+            origin: track.origin.clone(),
+            decoder_seeks: track.decoder_seeks,
+            duration_estimated: track.duration_estimated,
+            seek_index: track.seek_index.clone(),
+            sparse_index: track.sparse_index.clone(),
+            clock,
+            metadata: track.metadata.clone(),
+            cover_art: track.cover_art.clone(),
+            format: None,
+            playlist_index: None,
+            loudness: track.loudness,
+            gain: Arc::new(AtomicU32::new(1.0f32.to_bits())),
+        };
+        self.unload();
+
+        Ok(HandedOverTrack { track, elapsed, paused })
+    }
+
+    /// Loads a track taken out of another player with `hand_over_track()`, the second half of
+    /// `take_track_from()`.
+    pub fn take_over_track(&mut self, handed_over: HandedOverTrack) -> PlayerResult<()> {
+        self.ensure_device()?;
+        let HandedOverTrack {
+            mut track,
+            elapsed,
+            paused,
+        } = handed_over;
+        track.decoder_seeks = Self::decoder_seeks(
+            self.config.decoder_backend,
+            track.seek_index.is_some() || track.sparse_index.is_some(),
+        );
+        track.gain = Arc::new(AtomicU32::new(
+            Self::normalization_gain(self.normalization_target, track.loudness).to_bits(),
+        ));
+        self.unload();
+
+        let mut media = track
+            .file_handle
+            .try_clone()
+            .map_err(|error| PlayerError::UnableToCloneFileHandle(error.kind()))?;
+        media
+            .seek(std::io::SeekFrom::Start(0))
+            .map_err(|error| PlayerError::NotAbleToSeek(error.kind()))?;
+
+        // Stay paused until the position has been restored, so the start of the track isn't heard.
+        self.sink.pause();
+        track.format = Some(self.append_source(media, Duration::ZERO, &track)?);
+        self.tracks.push_back(track);
+        self.stopped = false;
+
+        if let Some(elapsed) = elapsed.filter(|_| self.is_seekable()) {
+            let _ = self.seek(elapsed);
+        }
+        if !paused {
+            self.sink.play();
+        }
+
+        Ok(())
+    }
+
+    /// Decodes from the current position of `media` and queues the result on the sink. `start` is
+    /// the time in `track` that the position corresponds to, which the source counts up from on
+    /// the track's clock. Returns the format the decoder found.
+    fn append_source(&self, media: MediaHandle, start: Duration, track: &Track) -> PlayerResult<AudioFormat> {
+        if track.origin == PlayerSource::Silence {
+            return Ok(self.append_silent(start, track));
+        }
+
+        let repeat_file_handle = match media.try_clone() {
+            Ok(media_handle) => media_handle,
+            Err(error) => return Err(PlayerError::UnableToCloneFileHandle(error.kind())),
+        };
+
+        let source = AudioDecoder::open_at(media, self.decoder_settings(track), start)?;
+        let format = AudioFormat::of(&source);
+        self.append_decoder(source, repeat_file_handle, start, track);
+
+        Ok(format)
+    }
+
+    /// Queues the rest of a gap from `append_silence()` from `start` on.
+    fn append_silent(&self, start: Duration, track: &Track) -> AudioFormat {
+        let format = track.format.unwrap_or(DEFAULT_SILENCE_FORMAT);
+        let source = Silence::new(
+            format.sample_rate,
+            format.channels,
+            start,
+            Arc::clone(&track.clock),
+            Arc::clone(&self.now_playing),
+        );
+        let source = Metered::new(source, Arc::clone(&self.levels));
+        self.sink.append(Signalled::new(source, self.finished_signal()));
+        format
+    }
+
+    fn append_decoder(&self, source: AudioDecoder, repeat_file_handle: MediaHandle, start: Duration, track: &Track) {
+        track.clock.set_loop_gap(self.loop_gap);
+        let source = Repeating::new(
+            source,
+            repeat_file_handle,
+            self.decoder_settings(track),
+            Arc::clone(&self.repeat_mode),
+            start,
+            Arc::clone(&track.clock),
+            Arc::clone(&self.now_playing),
+        );
+        let source = Upmixed::new(source, self.config.force_stereo);
+        let source = Resampled::new(source, self.target_sample_rate);
+        let source = Amplified::new(source, Arc::clone(&track.gain));
+        let source = Equalized::new(source, Arc::clone(&self.equalizer));
+        let source = Panned::new(source, Arc::clone(&self.balance));
+        let source = Metered::new(source, Arc::clone(&self.levels));
+        self.sink.append(Signalled::new(source, self.finished_signal()));
+    }
+
+    /// What a track's source runs once it has played out, counting the track as finished and
+    /// firing the finished callback. Stopping, clearing or skipping drops the source before then,
+    /// so it only runs on natural completion.
+    fn finished_signal(&self) -> Box<dyn FnOnce() + Send> {
+        let finished_tracks = Arc::clone(&self.finished_tracks);
+        let on_finished = Arc::clone(&self.on_finished);
+        Box::new(move || {
+            finished_tracks.fetch_add(1, Ordering::SeqCst);
+            if let Some(callback) = on_finished.lock().unwrap_or_else(PoisonError::into_inner).as_ref() {
+                callback();
+            }
+        })
+    }
+
+    /// The seek index of the current track, as the times frames start at along with their byte
+    /// offsets in the file, sorted by time. Thinned by `PlayerConfig::seek_granularity` if that is
+    /// set. `None` for tracks without one, including MP3s that get a sparse index instead.
+    pub fn seek_index(&self) -> Option<&[(Duration, u64)]> {
+        self.current_track().and_then(|track| track.seek_index.as_deref())
+    }
+
+    fn get_bytes_offset_for_time(&self, time: Duration) -> PlayerResult<u64> {
+        let seek_index = self.seek_index().ok_or(PlayerError::NoSeekIndex)?;
+        Ok(Self::indexed_offset_for_time(seek_index, time))
+    }
+
+    fn get_time_for_bytes_offset(&self, offset: u64) -> PlayerResult<Duration> {
+        let seek_index = self.seek_index().ok_or(PlayerError::NoSeekIndex)?;
+        Ok(Self::indexed_time_for_offset(seek_index, offset))
+    }
+
+    /// The byte offset of the frame in `seek_index` to start from to play from `time`.
+    fn indexed_offset_for_time(seek_index: &[(Duration, u64)], time: Duration) -> u64 {
+        // The index is sorted by time, so the frame to start from is the last one at or before it.
+        let frame = seek_index.partition_point(|(frame_time, _)| *frame_time <= time);
+        match frame {
+            0 => 0,
+            frame => seek_index[frame - 1].1,
+        }
+    }
+
+    /// The start time of the frame in `seek_index` that the byte `offset` is in.
+    fn indexed_time_for_offset(seek_index: &[(Duration, u64)], offset: u64) -> Duration {
+        // Offsets grow along with the times, so the same search works by offset.
+        let frame = seek_index.partition_point(|(_, frame_offset)| *frame_offset <= offset);
+        match frame {
+            0 => Duration::from_secs(0),
+            frame => seek_index[frame - 1].0,
+        }
+    }
+
+    /// Finds the frame to start decoding from to play from `time`, and returns its byte offset
+    /// along with the time it starts at. Large MP3s are scanned as far as needed.
+    fn frame_at(&mut self, time: Duration) -> PlayerResult<(u64, Duration)> {
+        if self.seek_index().is_some() {
+            let bytes_offset = self.get_bytes_offset_for_time(time)?;
+            return Ok((bytes_offset, self.get_time_for_bytes_offset(bytes_offset)?));
+        }
+
+        self.sync_tracks();
+        let track = match self.tracks.front_mut() {
+            // The decoder is started from the beginning of the file and seeks from there.
+            Some(track) if track.decoder_seeks => return Ok((0, time)),
+            Some(track) if track.sparse_index.is_some() => track,
+            _ => return Err(PlayerError::NoSeekIndex),
+        };
+
+        // A file of its own keeps the scan away from the decoder's cursor. A file handle passed in
+        // by the caller can only share it, so its position is put back once the scan is done.
+        let (reader, restore_position) = match &track.origin {
+            PlayerSource::Path(path) => {
+                let file = std::fs::File::open(path).map_err(|error| PlayerError::unable_to_open(path, error))?;
+                (MediaHandle::File(file), None)
+            }
+            _ => {
+                let mut reader = track
+                    .file_handle
+                    .try_clone()
+                    .map_err(|error| PlayerError::UnableToCloneFileHandle(error.kind()))?;
+                let position = reader
+                    .stream_position()
+                    .map_err(|error| PlayerError::NotAbleToSeek(error.kind()))?;
+                (reader, Some(position))
+            }
+        };
+
+        let sparse_index = track.sparse_index.as_mut().ok_or(PlayerError::NoSeekIndex)?;
+        let frame = sparse_index.find_frame(&reader, time);
+        if let Some(position) = restore_position {
+            (&reader)
+                .seek(std::io::SeekFrom::Start(position))
+                .map_err(|error| PlayerError::NotAbleToSeek(error.kind()))?;
+        }
+
+        frame
+            .map_err(|error| PlayerError::NotAbleToSeek(error.kind()))?
+            .ok_or(PlayerError::NotAbleToSeek(std::io::ErrorKind::InvalidData))
+    }
+
+    /// Whether the current track has a seek index, or is an MP3 large enough to be scanned as
+    /// seeks need it instead.
+    pub fn is_seekable(&self) -> bool {
+        self.current_track()
+            .is_some_and(|track| track.seek_index.is_some() || track.sparse_index.is_some() || track.decoder_seeks)
+    }
+
+    /// Restarts decoding of the current track from the frame at `time_offset`. The sink is cleared
+    /// first so the old decoder stops reading from the shared file cursor, then a new one is
+    /// appended in its place, followed by the rest of the queue. Volume and the play/pause state
+    /// carry over.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), err(Debug)))]
+    pub fn seek(&mut self, time_offset: Duration) -> PlayerResult<()> {
+        self.ensure_device()?;
+        let time_offset = match self.current_track() {
+            Some(track) => track.clock.track_time(time_offset),
+            None => time_offset,
+        };
+        self.seek_track_time(time_offset)
+    }
+
+    /// Seeks to a time in the untrimmed track, see `set_skip_silence()`.
+    fn seek_track_time(&mut self, time: Duration) -> PlayerResult<()> {
+        let (bytes_offset, frame_time) = self.frame_at(time)?;
+        self.seek_to_bytes_offset(bytes_offset, frame_time)
+    }
+
+    /// `frame_time` is the time in the track that `bytes_offset` starts at.
+    fn seek_to_bytes_offset(&mut self, bytes_offset: u64, frame_time: Duration) -> PlayerResult<()> {
+        self.ensure_device()?;
+        self.sync_tracks();
+
+        let track = match self.tracks.front() {
+            Some(track) => track,
+            None => return Err(PlayerError::NoFileHandle),
+        };
+        let mut file_handle = &track.file_handle;
+        let media = file_handle
+            .try_clone()
+            .map_err(|error| PlayerError::UnableToCloneFileHandle(error.kind()))?;
+
+        let paused = self.sink.is_paused();
+        self.sink.clear();
+        // The cleared finished signals will never fire, and a track that already finished is
+        // about to be played again.
+        self.synced_finished_tracks = self.finished_tracks.load(Ordering::SeqCst);
+
+        file_handle
+            .seek(std::io::SeekFrom::Start(bytes_offset))
+            .map_err(|error| PlayerError::NotAbleToSeek(error.kind()))?;
+
+        self.append_source(media, frame_time, track)?;
+        for track in self.tracks.iter().skip(1) {
+            let mut media = track
+                .file_handle
+                .try_clone()
+                .map_err(|error| PlayerError::UnableToCloneFileHandle(error.kind()))?;
+            media
+                .seek(std::io::SeekFrom::Start(0))
+                .map_err(|error| PlayerError::NotAbleToSeek(error.kind()))?;
+            self.append_source(media, Duration::ZERO, track)?;
+        }
+
+        self.stopped = false;
+        self.start_position = None;
+        if !paused {
+            self.sink.play();
+        }
+
+        Ok(())
+    }
+
+    /// Seeks to an exact sample of the current track, counted per channel from the start, for
+    /// editing where a `Duration` isn't precise enough. Decoding restarts at the frame on or
+    /// before the sample, like `seek()`, and drops the samples up to it before playing anything.
+    ///
+    /// The seek index maps byte offsets to the times its frames start at, which are only as
+    /// precise as the analyzer's timestamps. For compressed formats the frame is found from those
+    /// times, so landing on the exact sample relies on them matching what the decoder produces,
+    /// which is approximate for MP3s with encoder delay or a variable bitrate.
+    ///
+    /// Samples count from the start of the file, even when `set_skip_silence()` trims the track.
+    pub fn seek_samples(&mut self, sample: u64) -> PlayerResult<()> {
+        self.ensure_device()?;
+        let format = self.audio_format().ok_or(PlayerError::NoFileHandle)?;
+        let time = Self::sample_time(sample, format.sample_rate);
+        let (bytes_offset, frame_time) = self.frame_at(time)?;
+
+        let frame_sample = Self::time_sample(frame_time, format.sample_rate);
+        let skip = sample.saturating_sub(frame_sample) * u64::from(format.channels);
+        if let Some(track) = self.current_track() {
+            track.clock.skip_on_start(skip);
+        }
+
+        let result = self.seek_to_bytes_offset(bytes_offset, frame_time);
+        // Don't leave the skip behind for whichever source gets created for the track next.
+        if let (Err(_), Some(track)) = (&result, self.current_track()) {
+            track.clock.skip_on_start(0);
+        }
+
+        result
+    }
+
+    /// Jumps to the start of the seek index entry after the one that is playing, to step through
+    /// a track frame by frame. Does nothing on the last entry. Needs a full seek index, so a
+    /// thinned one steps by its granularity, and tracks with only a sparse index or none at all
+    /// fail with `PlayerError::NoSeekIndex`.
+    pub fn seek_to_next_frame(&mut self) -> PlayerResult<()> {
+        self.step_frames(1)
+    }
+
+    /// Jumps to the start of the seek index entry before the one that is playing, like
+    /// `seek_to_next_frame()`. Does nothing on the first entry.
+    pub fn seek_to_prev_frame(&mut self) -> PlayerResult<()> {
+        self.step_frames(-1)
+    }
+
+    fn step_frames(&mut self, step: isize) -> PlayerResult<()> {
+        self.ensure_device()?;
+        let seek_index = self.seek_index().ok_or(PlayerError::NoSeekIndex)?;
+        let position = self
+            .current_track()
+            .map_or(Duration::ZERO, |track| track.clock.track_position());
+
+        let current = seek_index
+            .partition_point(|(frame_time, _)| *frame_time <= position)
+            .saturating_sub(1);
+        let (frame_time, bytes_offset) = match current.checked_add_signed(step).and_then(|target| seek_index.get(target)) {
+            Some(&entry) => entry,
+            None => return Ok(()),
+        };
+
+        self.seek_to_bytes_offset(bytes_offset, frame_time)
+    }
+
+    /// The sample of the current track that is playing, counted per channel from the start of the
+    /// file, like `seek_samples()` takes.
+    pub fn current_sample(&self) -> PlayerResult<u64> {
+        let elapsed = self.current_track().ok_or(PlayerError::NoFileHandle)?.clock.track_position();
+        let format = self.audio_format().ok_or(PlayerError::NoFileHandle)?;
+        Ok(Self::time_sample(elapsed, format.sample_rate))
+    }
+
+    fn sample_time(sample: u64, sample_rate: u32) -> Duration {
+        let nanos = u128::from(sample) * 1_000_000_000 / u128::from(sample_rate.max(1));
+        Duration::from_nanos(u64::try_from(nanos).unwrap_or(u64::MAX))
+    }
+
+    /// Rounds to the nearest sample, since times are truncated to whole nanoseconds.
+    fn time_sample(time: Duration, sample_rate: u32) -> u64 {
+        let sample = (time.as_nanos() * u128::from(sample_rate) + 500_000_000) / 1_000_000_000;
+        u64::try_from(sample).unwrap_or(u64::MAX)
+    }
+
+    /// Repeats the part of the current track between `start` and `end` until `clear_ab_loop()` is
+    /// called. Playback jumps back to the frame at or before `start` whenever it reaches `end`,
+    /// without a gap, and seeking past `end` jumps back right away. Loading another track or
+    /// moving on to the next queued one ends the loop.
+    ///
+    /// Needs a seek index, and `start` must come before `end`, which can't be past the end of the
+    /// track.
+    pub fn set_ab_loop(&mut self, start: Duration, end: Duration) -> PlayerResult<()> {
+        if !self.is_file_loaded() {
+            return Err(PlayerError::NoFileHandle);
+        }
+
+        if start >= end || self.duration().is_some_and(|duration| end > duration) {
+            return Err(PlayerError::InvalidLoop);
+        }
+
+        let clock = self.current_track().map(|track| Arc::clone(&track.clock));
+        let (start, end) = match &clock {
+            Some(clock) => (clock.track_time(start), clock.track_time(end)),
+            None => (start, end),
+        };
+        let (start_offset, start_time) = self.frame_at(start)?;
+        if let Some(clock) = clock {
+            clock.set_loop(start_offset, start_time, end);
+        }
+
+        Ok(())
+    }
+
+    pub fn clear_ab_loop(&mut self) {
+        if let Some(track) = self.current_track() {
+            track.clock.clear_loop();
+        }
+    }
+
+    /// Seeks `delta` past the current position, stopping at the end of the track when its duration
+    /// is known.
+    pub fn seek_forward(&mut self, delta: Duration) -> PlayerResult<()> {
+        let mut target = self.elapsed()?.saturating_add(delta);
+        if let Some(duration) = self.duration() {
+            target = target.min(duration);
+        }
+
+        self.seek(target)
+    }
+
+    /// Seeks `delta` before the current position, stopping at the start of the track.
+    pub fn seek_backward(&mut self, delta: Duration) -> PlayerResult<()> {
+        let target = self.elapsed()?.saturating_sub(delta);
+
+        self.seek(target)
+    }
+
+    /// Seeks to a fraction of the track, where `0.0` is the start and `1.0` the end. Requires the
+    /// duration to be known.
+    pub fn seek_percent(&mut self, percent: f32) -> PlayerResult<()> {
+        let duration = self.duration().ok_or(PlayerError::UnableToGetDuration)?;
+        let percent = match percent.is_nan() {
+            true => 0.0,
+            false => percent.clamp(0.0, 1.0),
+        };
+
+        // `mul_f32()` goes through an `f32` of seconds, which can round past the largest duration.
+        let target = Duration::try_from_secs_f64(duration.as_secs_f64() * f64::from(percent)).unwrap_or(duration);
+        self.seek(target.min(duration))
+    }
+
+    /// Starts or continues playback. A stopped track starts over from the beginning, unless a
+    /// start position was set. Fails with `PlayerError::DeviceLost` while the output device is
+    /// gone, keeping the start position for when playback has moved to another device.
+    pub fn play(&mut self) -> PlayerResult<()> {
+        trace::debug!("play", state = self.state(), start_position = self.start_position);
+        self.ensure_device()?;
+        let started = match self.start_position.take() {
+            Some(position) => self.seek(position).is_ok(),
+            None => false,
+        };
+        if !started && self.stopped && self.is_file_loaded() {
+            let _ = self.seek_to_bytes_offset(0, Duration::ZERO);
+        }
+        self.sink.play();
+
+        Ok(())
+    }
+
+    /// Makes the next `play()` start from `position` in the loaded track, instead of seeking
+    /// right away. Restoring saved state can then load a file with `prefetch()` and set where it
+    /// left off, and nothing is heard until the user presses play, not even the start of the
+    /// track before an immediate seek lands.
+    ///
+    /// Loading another track, unloading, or seeking before then drops the start position. If the
+    /// track can't be seeked to it, `play()` carries on from where the track is.
+    pub fn set_start_position(&mut self, position: Duration) {
+        self.start_position = Some(position);
+    }
+
+    /// Continues playback if the player is paused, and does nothing otherwise.
+    pub fn resume(&mut self) {
+        if self.sink.is_paused() {
+            self.sink.play();
+        }
+    }
+
+    /// Pauses if the player is playing and plays otherwise, for a play/pause button. A stopped
+    /// track starts over like with `play()`, while a finished one stays finished.
+    pub fn toggle(&mut self) -> PlayerResult<()> {
+        match self.is_playing() {
+            true => {
+                self.pause();
+                Ok(())
+            }
+            false => self.play(),
+        }
+    }
+
+    /// Plays the loaded track from the beginning. Unlike `seek()` this doesn't need a seek index,
+    /// since the start of the track is always at the start of the file.
+    pub fn restart(&mut self) -> PlayerResult<()> {
+        self.seek_to_bytes_offset(0, Duration::ZERO)?;
+        self.sink.play();
+
+        Ok(())
+    }
+
+    pub fn pause(&mut self) {
+        trace::debug!("pause", state = self.state());
+        self.sink.pause();
+    }
+
+    /// Halts playback and drops everything queued after the current track. The current track
+    /// stays loaded, rewound to the start, so `play()`, `restart()` or `seek()` can start it again
+    /// without opening the file once more. Use `unload()` to let go of the file as well.
+    pub fn stop(&mut self) {
+        trace::debug!("stop", state = self.state());
+        self.sync_tracks();
+        self.tracks.truncate(1);
+        self.synced_finished_tracks = self.finished_tracks.load(Ordering::SeqCst);
+        self.cancel_fade();
+        self.sink.stop();
+        *self.now_playing.lock().unwrap_or_else(PoisonError::into_inner) = None;
+        self.stopped = true;
+
+        if let Some(track) = self.tracks.front() {
+            track.clock.rewind();
+        }
+    }
+
+    /// Stops playback and lets go of the loaded file along with everything read from it, like the
+    /// seek index, tags and cover art, so `is_file_loaded()` is false afterwards and the state is
+    /// back to `Empty`. The output stream and sink stay open, so loading the next file is as
+    /// quick as on a fresh player and settings like volume and speed carry over.
+    pub fn unload(&mut self) {
+        self.stop();
+        self.tracks = VecDeque::new();
+        self.stopped = false;
+        self.start_position = None;
+    }
+
+    /// Blocks the current thread until everything queued has played through, or returns straight
+    /// away if nothing is playing. With a repeat mode other than `Off` this never returns on its
+    /// own, and a paused player keeps it waiting.
+    pub fn wait_until_end(&self) {
+        self.sink.sleep_until_end();
+    }
+
+    /// Resolves once everything queued has played through, like `wait_until_end()` but without
+    /// blocking the thread. The returned future doesn't borrow the player, so a caller inside a
+    /// lock can take it, release the lock and only then await it.
+    pub fn wait_until_end_async(&self) -> impl std::future::Future<Output = ()> + Send + 'static {
+        let sink = Arc::clone(&self.sink);
+        async move {
+            while !sink.empty() {
+                tokio::time::sleep(WAIT_POLL_INTERVAL).await;
+            }
+        }
+    }
+
+    pub fn state(&self) -> PlaybackState {
+        if self.output.lost.load(Ordering::SeqCst) {
+            return PlaybackState::DeviceLost;
+        }
+        if !self.is_file_loaded() {
+            return PlaybackState::Empty;
+        }
+
+        if self.stopped {
+            PlaybackState::Stopped
+        } else if self.sink.empty() {
+            PlaybackState::Finished
+        } else if self.sink.is_paused() {
+            PlaybackState::Paused
+        } else {
+            PlaybackState::Playing
+        }
+    }
+
+    /// Whether a track is loaded and the sink is playing it. Finished and stopped tracks aren't
+    /// playing, and neither is an empty player even though its sink isn't paused.
+    pub fn is_playing(&self) -> bool {
+        self.state() == PlaybackState::Playing
+    }
+
+    /// Whether a track is loaded and paused partway through.
+    pub fn is_paused(&self) -> bool {
+        self.state() == PlaybackState::Paused
+    }
+
+    /// How far into the current track playback is. This is counted from the samples the sink has
+    /// actually pulled from the decoder rather than from the file cursor, which runs ahead of
+    /// playback because decoding is buffered. It holds still while paused and once the track has
+    /// finished, and works for tracks without a seek index too.
+    ///
+    /// The samples are counted before `set_speed()` resamples them, so at 2x a second of playing
+    /// moves it on by two seconds of the track, and changing speed partway through needs no
+    /// bookkeeping.
+    pub fn elapsed(&self) -> PlayerResult<Duration> {
+        match self.current_track() {
+            Some(track) => Ok(track.clock.elapsed()),
+            None => Err(PlayerError::NoFileHandle),
+        }
+    }
+
+    /// The tags of the current track, read once when it was loaded.
+    pub fn metadata(&self) -> Option<&TrackMetadata> {
+        self.current_track().map(|track| &track.metadata)
+    }
+
+    /// The picture embedded in the current track's tags, if it has one. It is read along with the
+    /// tags when the track is loaded, so this doesn't touch the file again.
+    pub fn cover_art(&self) -> Option<CoverArt> {
+        self.current_track().and_then(|track| track.cover_art.clone())
+    }
+
+    /// The sample rate and channel count of the current track, as reported by the decoder when the
+    /// track was loaded.
+    pub fn audio_format(&self) -> Option<AudioFormat> {
+        self.current_track().and_then(|track| track.format)
+    }
+
+    /// The channel layout of the current track, as found by the decoder when the track was
+    /// loaded. Mono tracks report `Stereo` when `PlayerConfig::force_stereo` upmixes them.
+    pub fn channel_layout(&self) -> Option<ChannelLayout> {
+        let layout = ChannelLayout::of(self.audio_format()?.channels);
+        match (layout, self.config.force_stereo) {
+            (ChannelLayout::Mono, true) => Some(ChannelLayout::Stereo),
+            (layout, _) => Some(layout),
+        }
+    }
+
+    pub fn duration(&self) -> Option<Duration> {
+        self.current_track().and_then(|track| track.clock.duration())
+    }
+
+    /// Whether `duration()` is only an estimate, worked out from the bitrate of a file that
+    /// neither the analyzer nor the tags had a duration for, so the UI can mark it as approximate.
+    /// It is exact for constant bitrate files, but can be well off for variable bitrate ones.
+    pub fn duration_is_estimated(&self) -> bool {
+        self.current_track().is_some_and(|track| track.duration_estimated)
+    }
+
+    /// A snapshot of the player's state for troubleshooting. It only reads what the player
+    /// already knows, so it never decodes or touches the file and is cheap enough to poll.
+    pub fn diagnostics(&self) -> PlayerDiagnostics {
+        PlayerDiagnostics {
+            loaded: self.is_file_loaded(),
+            seekable: self.is_seekable(),
+            has_duration: self.duration().is_some(),
+            has_metadata: self
+                .metadata()
+                .is_some_and(|metadata| metadata.title.is_some() || metadata.artist.is_some() || metadata.album.is_some()),
+            queue_len: self.queue_len(),
+            volume: self.volume(),
+            state: self.state(),
+            format: self.audio_format(),
+        }
+    }
+
+    /// Returns the elapsed time and duration together, so both come from the same moment.
+    pub fn progress(&self) -> PlayerResult<PlaybackProgress> {
+        let elapsed = self.elapsed()?;
+        Ok(PlaybackProgress::new(elapsed, self.duration()))
+    }
+
+    /// Sends a progress snapshot every `interval` while the player is playing, so the front end
+    /// doesn't have to poll for one. The snapshots come from a task on the tokio runtime, or a
+    /// background thread when called outside of one, which stops once everything queued has
+    /// played through, the receiver is dropped, or the player is dropped or switches devices.
+    /// Snapshots the receiver hasn't caught up with are dropped rather than queued.
+    pub fn subscribe_progress(&self, interval: Duration) -> tokio::sync::mpsc::Receiver<PlaybackProgress> {
+        let (sender, receiver) = tokio::sync::mpsc::channel(PROGRESS_CHANNEL_CAPACITY);
+        let interval = interval.max(MIN_PROGRESS_INTERVAL);
+        let cancel = self.progress_cancel.clone();
+        let sink = Arc::clone(&self.sink);
+        let now_playing = Arc::clone(&self.now_playing);
+
+        // Sends the snapshot for right now, returning whether there will be more.
+        let send_progress = move || {
+            if sender.is_closed() {
+                return false;
+            }
+            let clock = match now_playing.lock().unwrap_or_else(PoisonError::into_inner).as_ref() {
+                Some(clock) => Arc::clone(clock),
+                None => return true,
+            };
+
+            let finished = sink.empty();
+            if sink.is_paused() && !finished {
+                return true;
+            }
+
+            let _ = sender.try_send(PlaybackProgress::new(clock.elapsed(), clock.duration()));
+            !finished
+        };
+
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => {
+                runtime.spawn(async move {
+                    loop {
+                        let mut sleep = std::pin::pin!(tokio::time::sleep(interval));
+                        let mut cancelled = std::pin::pin!(cancel.cancelled());
+                        let cancelled = std::future::poll_fn(|cx| match cancelled.as_mut().poll(cx) {
+                            Poll::Ready(()) => Poll::Ready(true),
+                            Poll::Pending => sleep.as_mut().poll(cx).map(|()| false),
+                        })
+                        .await;
+                        if cancelled || !send_progress() {
+                            return;
+                        }
+                    }
+                });
+            }
+            Err(_) => {
+                std::thread::spawn(move || loop {
+                    std::thread::sleep(interval);
+                    if cancel.is_cancelled() || !send_progress() {
+                        return;
+                    }
+                });
+            }
+        }
+
+        receiver
+    }
+
+    /// The volume set with `set_volume()`, before the volume curve is applied. Fades ramp the
+    /// output towards or away from this level without changing it.
+    pub fn volume(&self) -> f32 {
+        self.volume
+    }
+
+    /// Sets the volume, cancelling any fade that is still running. While muted the new level is
+    /// only remembered and takes effect on `unmute()`.
+    pub fn set_volume(&mut self, volume: f32) {
+        self.cancel_fade();
+        self.volume = volume;
+        self.faded_volume.store(volume.to_bits(), Ordering::SeqCst);
+        self.sink.set_volume(self.output_volume());
+    }
+
+    pub fn volume_curve(&self) -> VolumeCurve {
+        self.volume_curve
+    }
+
+    pub fn set_volume_curve(&mut self, curve: VolumeCurve) {
+        self.cancel_fade();
+        self.volume_curve = curve;
+        self.faded_volume.store(self.volume.to_bits(), Ordering::SeqCst);
+        self.sink.set_volume(self.output_volume());
+    }
+
+    /// Scales this player's volume, so `Music` can offer master and group volumes on top of the
+    /// per-player ones. A fade that is still running carries on within the new level.
+    pub(crate) fn set_master_volume(&mut self, volume: f32) {
+        self.master_volume.store(volume.to_bits(), Ordering::SeqCst);
+        self.sink.set_volume(self.output_volume());
+    }
+
+    /// The level the sink should be at for the current faded volume, master volume, curve and
+    /// mute state.
+    fn output_volume(&self) -> f32 {
+        match self.muted {
+            true => 0.0,
+            false => Self::sink_volume(
+                self.volume_curve,
+                f32::from_bits(self.faded_volume.load(Ordering::SeqCst)),
+                &self.master_volume,
+            ),
+        }
+    }
+
+    /// The master volume scales the volume before the curve, so both sliders feel the same.
+    fn sink_volume(curve: VolumeCurve, volume: f32, master_volume: &AtomicU32) -> f32 {
+        curve.amplitude(volume * f32::from_bits(master_volume.load(Ordering::SeqCst)))
+    }
+
+    pub fn is_muted(&self) -> bool {
+        self.muted
+    }
+
+    /// Silences the output while keeping `volume()` so `unmute()` can bring it back.
+    pub fn mute(&mut self) {
+        self.cancel_fade();
+        self.faded_volume.store(self.volume.to_bits(), Ordering::SeqCst);
+        self.muted = true;
+        self.sink.set_volume(0.0);
+    }
+
+    pub fn unmute(&mut self) {
+        self.muted = false;
+        self.sink.set_volume(self.output_volume());
+    }
+
+    /// Starts playback from silence and ramps up to `volume()` over `duration`. Playback starts
+    /// like `play()` does, so a pending start position is seeked to and a stopped track restarts
+    /// from the beginning.
+    pub fn fade_in(&mut self, duration: Duration) -> PlayerResult<()> {
+        self.start_fade(0.0, self.volume, duration, false);
+        self.play()
+    }
+
+    /// Ramps the current output down to silence over `duration`. When `pause` is set the player
+    /// pauses once silent and the volume is restored for the next `play()`; otherwise the output
+    /// stays silent until `fade_in()` or `set_volume()` is called.
+    pub fn fade_out(&mut self, duration: Duration, pause: bool) {
+        let from = f32::from_bits(self.faded_volume.load(Ordering::SeqCst));
+        self.start_fade(from, 0.0, duration, pause);
+    }
+
+    fn cancel_fade(&self) {
+        self.fade_generation.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Ramps the faded volume from `from` to `to` on a background thread, and keeps the sink at
+    /// that volume scaled by the master volume as it is at each step. Every new fade bumps the
+    /// generation counter, which makes any fade still in flight give up on its next step.
+    fn start_fade(&self, from: f32, to: f32, duration: Duration, pause_at_end: bool) {
+        let restore_volume = match pause_at_end {
+            true => self.volume,
+            false => to,
+        };
+
+        if self.muted {
+            self.cancel_fade();
+            self.faded_volume.store(restore_volume.to_bits(), Ordering::SeqCst);
+            if pause_at_end {
+                self.sink.pause();
+            }
+            return;
+        }
+
+        let generation = self.fade_generation.fetch_add(1, Ordering::SeqCst) + 1;
+        let fade_generation = Arc::clone(&self.fade_generation);
+        let target = self.fade_target();
+
+        target.set(from);
+        std::thread::spawn(move || {
+            let started = Instant::now();
+            loop {
+                if fade_generation.load(Ordering::SeqCst) != generation {
+                    return;
+                }
+
+                let progress = match duration.is_zero() {
+                    true => 1.0,
+                    false => (started.elapsed().as_secs_f32() / duration.as_secs_f32()).min(1.0),
+                };
+                target.set(from + (to - from) * progress);
+
+                if progress >= 1.0 {
+                    break;
+                }
+                std::thread::sleep(FADE_STEP);
+            }
+
+            if pause_at_end {
+                target.sink.pause();
+                target.set(restore_volume);
+            }
+        });
+    }
+
+    fn fade_target(&self) -> FadeTarget {
+        FadeTarget {
+            sink: Arc::clone(&self.sink),
+            faded_volume: Arc::clone(&self.faded_volume),
+            master_volume: Arc::clone(&self.master_volume),
+            curve: self.volume_curve,
+        }
+    }
+
+    /// Fades out and pauses once the player has been playing for `after`, for falling asleep to
+    /// music. Only time spent playing counts, so pausing holds the timer too. The fade takes five
+    /// seconds, and setting the volume, muting or starting another fade during it cancels the
+    /// pause. Master and group volume changes during it are followed, like in any other fade.
+    ///
+    /// Setting another timer replaces this one. Switching output devices carries it over with
+    /// the time it has left, while dropping the player clears it.
+    pub fn set_sleep_timer(&mut self, after: Duration) {
+        let generation = {
+            let mut left = self.sleep_timer_left.lock().unwrap_or_else(PoisonError::into_inner);
+            *left = Some(after);
+            self.sleep_timer_generation.fetch_add(1, Ordering::SeqCst) + 1
+        };
+        let sleep_timer_generation = Arc::clone(&self.sleep_timer_generation);
+        let sleep_timer_left = Arc::clone(&self.sleep_timer_left);
+        let fade_generation = Arc::clone(&self.fade_generation);
+        let target = self.fade_target();
+        let sink = Arc::clone(&self.sink);
+
+        std::thread::spawn(move || {
+            let is_current = || sleep_timer_generation.load(Ordering::SeqCst) == generation;
+            let set_left = |left: Option<Duration>| {
+                let mut sleep_timer_left = sleep_timer_left.lock().unwrap_or_else(PoisonError::into_inner);
+                // Checked under the lock, so a timer that has just been replaced or cleared
+                // doesn't write over what took its place.
+                if is_current() {
+                    *sleep_timer_left = left;
+                }
+            };
+            let mut played = Duration::ZERO;
+            let mut last_check = Instant::now();
+            while played < after {
+                std::thread::sleep(WAIT_POLL_INTERVAL);
+                if !is_current() {
+                    return;
+                }
+
+                let now = Instant::now();
+                if !sink.is_paused() && !sink.empty() {
+                    played += now - last_check;
+                }
+                last_check = now;
+                set_left(Some(after.saturating_sub(played)));
+            }
+
+            // The fade leaves the player's own volume alone and restores the faded volume it
+            // started from, so playing again after the pause is at the usual level.
+            let fade = fade_generation.fetch_add(1, Ordering::SeqCst) + 1;
+            let volume = f32::from_bits(target.faded_volume.load(Ordering::SeqCst));
+            let started = Instant::now();
+            loop {
+                // Another fade or volume change has set the volume on its own.
+                if fade_generation.load(Ordering::SeqCst) != fade {
+                    set_left(None);
+                    return;
+                }
+                if !is_current() {
+                    target.set(volume);
+                    return;
+                }
+
+                let progress = (started.elapsed().as_secs_f32() / SLEEP_TIMER_FADE.as_secs_f32()).min(1.0);
+                target.set(volume * (1.0 - progress));
+                if progress >= 1.0 {
+                    break;
+                }
+                std::thread::sleep(FADE_STEP);
+            }
+
+            trace::debug!("the sleep timer paused playback", after = after);
+            target.sink.pause();
+            target.set(volume);
+            set_left(None);
+        });
+    }
+
+    /// Cancels the sleep timer set with `set_sleep_timer()`, if there is one. If it has already
+    /// started fading out, the volume goes back to where it was and playback carries on.
+    pub fn clear_sleep_timer(&mut self) {
+        let mut left = self.sleep_timer_left.lock().unwrap_or_else(PoisonError::into_inner);
+        *left = None;
+        self.sleep_timer_generation.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// The left and right peak levels of what is playing, from `0.0` to `1.0` with the volume
+    /// applied. Mono audio reports the same level twice, and a player that is paused or done
+    /// playing reports silence. Returns `None` if nothing is loaded.
+    ///
+    /// The levels are measured over the last 50 ms of samples handed to the sink, which runs a
+    /// little ahead of what is audible by however much audio the output device buffers, usually
+    /// a few tens of milliseconds.
+    pub fn current_levels(&self) -> Option<(f32, f32)> {
+        if !self.is_file_loaded() {
+            return None;
+        }
+
+        if self.sink.is_paused() || self.sink.empty() {
+            return Some((0.0, 0.0));
+        }
+
+        let volume = self.sink.volume();
+        let (left, right) = self.levels.get();
+        Some(((left * volume).min(1.0), (right * volume).min(1.0)))
+    }
+
+    pub fn equalizer(&self) -> Equalizer {
+        self.equalizer.get()
+    }
+
+    /// Replaces the equalizer. The playing track picks up the change right away, and it applies to
+    /// everything queued or loaded later too.
+    pub fn set_equalizer(&mut self, equalizer: Equalizer) {
+        self.equalizer.set(equalizer);
+    }
+
+    pub fn balance(&self) -> f32 {
+        f32::from_bits(self.balance.load(Ordering::SeqCst))
+    }
+
+    /// Pans the output, from `-1.0` for only the left speaker through `0.0` for both to `1.0` for
+    /// only the right one. It applies to the playing track right away. NaN is taken as `0.0`.
+    pub fn set_balance(&mut self, pan: f32) -> PlayerResult<()> {
+        let pan = match pan.is_nan() {
+            true => 0.0,
+            false => pan,
+        };
+        if !(-1.0..=1.0).contains(&pan) {
+            return Err(PlayerError::InvalidBalance);
+        }
+
+        self.balance.store(pan.to_bits(), Ordering::SeqCst);
+
+        Ok(())
+    }
+
+    pub fn normalization(&self) -> Option<f32> {
+        self.normalization_target
+    }
+
+    /// Plays every track at about `target_lufs`, e.g. `-14.0`, so the volume doesn't jump between
+    /// quiet and loud tracks. Each track's RMS level is measured by decoding it once, which is
+    /// cached alongside the seek index for files loaded by path, and a gain makes up the
+    /// difference to the target, boosting by at most 12 dB.
+    ///
+    /// The loaded tracks are measured right away and ones loaded later as they come in. A track
+    /// that is already playing from a handle passed in by the caller can't be read a second time
+    /// without disturbing playback, so it keeps playing at its own level.
+    pub async fn enable_normalization(&mut self, target_lufs: f32) {
+        self.normalization_target = Some(target_lufs);
+
+        for index in 0..self.tracks.len() {
+            let track = &self.tracks[index];
+            if track.loudness.is_none() {
+                let loudness = match (&track.origin, track.file_handle.independent_clone()) {
+                    (PlayerSource::Path(path), _) => {
+                        let cache_key = tokio::fs::metadata(path)
+                            .await
+                            .ok()
+                            .and_then(|metadata| CacheKey::new(path, &metadata));
+                        match tokio::fs::File::open(path).await {
+                            Ok(file) => {
+                                let media = MediaHandle::File(file.into_std().await);
+                                Self::measure_loudness(media, cache_key.as_ref(), self.config.decoder_backend).await
+                            }
+                            Err(_) => None,
+                        }
+                    }
+                    (_, Some(analysis_handle)) => {
+                        let backend = self.config.decoder_backend;
+                        Self::blocking(move || Self::rms_loudness(analysis_handle, backend)).await
+                    }
+                    _ => None,
+                };
+                self.tracks[index].loudness = loudness;
+            }
+
+            let track = &self.tracks[index];
+            track.gain.store(
+                Self::normalization_gain(self.normalization_target, track.loudness).to_bits(),
+                Ordering::SeqCst,
+            );
+        }
+    }
+
+    /// Decodes the current track and returns the peak amplitude, from `0.0` to `1.0`, of each of
+    /// `buckets` equal slices of it, for drawing a waveform. Decoding a whole track takes a
+    /// while, so the waveforms of files loaded by path are cached alongside their seek index.
+    ///
+    /// A file handle passed in by the caller can't be read a second time without disturbing
+    /// playback, so it fails with `PlayerError::UnableToCloneFileHandle`.
+    pub async fn compute_waveform(&self, buckets: usize) -> PlayerResult<Vec<f32>> {
+        let track = self.current_track().ok_or(PlayerError::NoFileHandle)?;
+        let (media, cache_key) = match (&track.origin, track.file_handle.independent_clone()) {
+            (PlayerSource::Path(path), _) => {
+                let file = tokio::fs::File::open(path)
+                    .await
+                    .map_err(|error| PlayerError::unable_to_open(path, error))?;
+                let cache_key = file.metadata().await.ok().and_then(|metadata| CacheKey::new(path, &metadata));
+                (MediaHandle::File(file.into_std().await), cache_key)
+            }
+            (_, Some(analysis_handle)) => (analysis_handle, None),
+            _ => return Err(PlayerError::UnableToCloneFileHandle(std::io::ErrorKind::Unsupported)),
+        };
+
+        if let Some(cache_key) = &cache_key {
+            let cached: Option<Vec<f32>> = cache::read(cache::WAVEFORM, cache_key).await;
+            if let Some(peaks) = cached.filter(|peaks| peaks.len() == buckets) {
+                return Ok(peaks);
+            }
+        }
+
+        let backend = self.config.decoder_backend;
+        let peaks = Self::blocking(move || Self::waveform_peaks(media, buckets, backend)).await?;
+        if let Some(cache_key) = &cache_key {
+            cache::write(cache::WAVEFORM, cache_key, &peaks).await;
+        }
+
+        Ok(peaks)
+    }
+
+    /// Decodes the whole track and returns the peak of every `WAVEFORM_CHUNK_FRAMES` frames,
+    /// along with the sample rate, so the whole track never has to be held in memory.
+    fn chunk_peaks(media: MediaHandle, backend: DecoderBackend) -> PlayerResult<(Vec<u16>, u32)> {
+        let decoder = AudioDecoder::new(media, Self::analysis_settings(backend))?;
+        let sample_rate = decoder.sample_rate();
+        let chunk_len = WAVEFORM_CHUNK_FRAMES * usize::from(decoder.channels().max(1));
+
+        let mut chunks = Vec::new();
+        let mut peak = 0u16;
+        for (index, sample) in decoder.enumerate() {
+            peak = peak.max(sample.unsigned_abs());
+            if (index + 1) % chunk_len == 0 {
+                chunks.push(peak);
+                peak = 0;
+            }
+        }
+        if peak > 0 {
+            chunks.push(peak);
+        }
+
+        Ok((chunks, sample_rate))
+    }
+
+    /// Combines the chunk peaks into `buckets` peaks.
+    fn waveform_peaks(media: MediaHandle, buckets: usize, backend: DecoderBackend) -> PlayerResult<Vec<f32>> {
+        let (chunks, _) = Self::chunk_peaks(media, backend)?;
+        if chunks.is_empty() {
+            return Ok(vec![0.0; buckets]);
+        }
+
+        // With more buckets than chunks, neighbouring buckets share a chunk.
+        let peaks = (0..buckets)
+            .map(|bucket| {
+                let start = (bucket * chunks.len() / buckets).min(chunks.len() - 1);
+                let end = ((bucket + 1) * chunks.len() / buckets).max(start + 1);
+                let peak = chunks[start..end].iter().copied().max().unwrap_or(0);
+                (f32::from(peak) / f32::from(i16::MAX)).min(1.0)
+            })
+            .collect();
+
+        Ok(peaks)
+    }
+
+    /// Skips the near-silence at the start and end of every track, for tracks with long lead-ins
+    /// or tails. Each track is decoded once to find where its peaks, the same ones a waveform is
+    /// drawn from, first and last rise above `silence_threshold()`. From then on
+    /// `elapsed()`, `duration()`, `seek()` and the A-B loop all work in the trimmed track, as if
+    /// the silence wasn't there.
+    ///
+    /// Tracks loaded from then on are analyzed as they load. The ones already loaded are analyzed
+    /// by the returned future, on the blocking pool. It doesn't borrow the player, so a caller
+    /// holding the player in a lock, like `Music::with_player_mut()`, can release the lock before
+    /// awaiting it, and `state()` or `elapsed()` aren't held up for the whole decode. A track
+    /// that is playing already has its trimmed start skipped the next time it starts from the
+    /// beginning. A track that is playing from a handle passed in by the caller can't be read a
+    /// second time without disturbing playback, so it is left untrimmed.
+    pub fn set_skip_silence(&mut self, enabled: bool) -> impl Future<Output = ()> + Send + 'static {
+        self.skip_silence = enabled;
+        if !enabled {
+            self.trim_generation.fetch_add(1, Ordering::SeqCst);
+            for track in &self.tracks {
+                track.clock.clear_trim();
+            }
+        }
+
+        self.retrim()
+    }
+
+    pub fn skip_silence(&self) -> bool {
+        self.skip_silence
+    }
+
+    pub fn silence_threshold(&self) -> f32 {
+        self.silence_threshold
+    }
+
+    /// Sets the peak level in dBFS, `-50.0` by default, that `set_skip_silence()` treats anything
+    /// quieter than as silence. Speech with quiet breaths between words wants a lower threshold
+    /// than music with a noisy fade-out. NaN is taken as the default.
+    ///
+    /// When silence is being skipped, the loaded tracks are trimmed again by the returned future,
+    /// off the player like with `set_skip_silence()`. Setting the threshold it already has does
+    /// nothing, and a newer threshold makes trims still being worked out for an older one give
+    /// up, so dragging a slider only decodes the tracks again for where it was let go.
+    pub fn set_silence_threshold(&mut self, db: f32) -> impl Future<Output = ()> + Send + 'static {
+        let db = match db.is_nan() {
+            true => DEFAULT_SILENCE_THRESHOLD_DB,
+            false => db,
+        };
+        let changed = db != self.silence_threshold;
+        self.silence_threshold = db;
+
+        match changed {
+            true => self.retrim(),
+            false => Box::pin(async {}),
+        }
+    }
+
+    /// Works out the trim of every loaded track again for the current settings, one track at a
+    /// time on the blocking pool. Only the tracks' clocks are touched, which the player shares.
+    fn retrim(&self) -> Pin<Box<dyn Future<Output = ()> + Send + 'static>> {
+        if !self.skip_silence {
+            return Box::pin(async {});
+        }
+
+        let generation = self.trim_generation.fetch_add(1, Ordering::SeqCst) + 1;
+        let trims: Vec<SilenceTrim> = self
+            .tracks
+            .iter()
+            .filter_map(|track| Self::silence_trim(track, self.config.decoder_backend, self.silence_threshold))
+            .collect();
+        let trim_generation = Arc::clone(&self.trim_generation);
+        Box::pin(async move {
+            for trim in trims {
+                let trim_generation = Arc::clone(&trim_generation);
+                let is_current = move || trim_generation.load(Ordering::SeqCst) == generation;
+                if !is_current() {
+                    return;
+                }
+                Self::blocking(move || trim(&is_current)).await;
+            }
+        })
+    }
+
+    /// Finds where the sound of `track` starts and ends, and has its clock trim the rest. This
+    /// decodes the whole track, so it blocks.
+    fn trim_silence(track: &Track, backend: DecoderBackend, threshold_db: f32) {
+        if let Some(trim) = Self::silence_trim(track, backend, threshold_db) {
+            trim(&|| true);
+        }
+    }
+
+    /// The work of trimming `track`, which can be done later without the player. It only applies
+    /// the trim if its check still passes once the track has been decoded. `None` for tracks
+    /// that can't be read a second time, which are left untrimmed.
+    fn silence_trim(track: &Track, backend: DecoderBackend, threshold_db: f32) -> Option<SilenceTrim> {
+        let open: Box<dyn FnOnce() -> Option<MediaHandle> + Send> =
+            match (&track.origin, track.file_handle.independent_clone()) {
+                (PlayerSource::Silence, _) => return None,
+                (PlayerSource::Path(path), _) => {
+                    let path = path.clone();
+                    Box::new(move || std::fs::File::open(path).ok().map(MediaHandle::File))
+                }
+                (_, Some(analysis_handle)) => Box::new(move || Some(analysis_handle)),
+                _ => return None,
+            };
+        let clock = Arc::clone(&track.clock);
+        let origin = track.origin.clone();
+
+        Some(Box::new(move |is_current| {
+            let range = open().and_then(|media| Self::audible_range(media, backend, threshold_db));
+            if !is_current() {
+                return;
+            }
+            match range {
+                Some((start, end)) => clock.set_trim(start, end),
+                None => trace::debug!("no audible range to trim the silence to", source = origin),
+            }
+        }))
+    }
+
+    /// The times the first and last chunk peaks above `threshold_db` start and end at. `None`
+    /// when the track can't be decoded or is silent all the way through, which is left untrimmed.
+    fn audible_range(media: MediaHandle, backend: DecoderBackend, threshold_db: f32) -> Option<(Duration, Duration)> {
+        let (chunks, sample_rate) = Self::chunk_peaks(media, backend).ok()?;
+        let amplitude = 10f32.powf(threshold_db / 20.0).clamp(0.0, 1.0);
+        let threshold = (amplitude * f32::from(i16::MAX)) as u16;
+        let first = chunks.iter().position(|peak| *peak > threshold)?;
+        let last = chunks.iter().rposition(|peak| *peak > threshold)?;
+
+        let chunk_time = |chunk: usize| Self::sample_time((chunk * WAVEFORM_CHUNK_FRAMES) as u64, sample_rate);
+        Some((chunk_time(first), chunk_time(last + 1)))
+    }
+
+    /// Turns normalization off, playing every track at its own level again.
+    pub fn disable_normalization(&mut self) {
+        self.normalization_target = None;
+        for track in &self.tracks {
+            track.gain.store(1f32.to_bits(), Ordering::SeqCst);
+        }
+    }
+
+    /// The gain that brings a track at `loudness` up or down to `target`.
+    fn normalization_gain(target: Option<f32>, loudness: Option<f32>) -> f32 {
+        match (target, loudness) {
+            (Some(target), Some(loudness)) => 10f32.powf((target - loudness).min(MAX_NORMALIZATION_GAIN_DB) / 20.0),
+            _ => 1.0,
+        }
+    }
+
+    pub fn speed(&self) -> f32 {
+        self.sink.speed()
+    }
+
+    /// Changes the playback speed, clamped to `0.25..=4.0`. A factor of `1.0` is normal speed.
+    ///
+    /// `elapsed()` counts the samples that have been played, so it keeps reporting the position
+    /// within the track rather than the wall-clock time spent playing it.
+    pub fn set_speed(&mut self, factor: f32) -> PlayerResult<()> {
+        if factor.is_nan() || factor <= 0.0 {
+            return Err(PlayerError::InvalidSpeed);
+        }
+
+        self.sink.set_speed(factor.clamp(MIN_SPEED, MAX_SPEED));
+
+        Ok(())
+    }
+
+    pub fn target_sample_rate(&self) -> Option<u32> {
+        self.target_sample_rate
+    }
+
+    /// Resamples every track to `hz` before it reaches the equalizer and the output, so players
+    /// mixed together all hand the output the same rate. `0` turns resampling off again, which is
+    /// the default, passing every track on at its own rate.
+    ///
+    /// The resampling is linear, see `Resampled` for what that costs and how it sounds. The
+    /// current track is decoded again from where it is right now if it can be seeked, and
+    /// otherwise keeps its rate until the next track. `audio_format()` still reports the rate of
+    /// the file.
+    pub fn set_target_sample_rate(&mut self, hz: u32) {
+        self.target_sample_rate = Some(hz).filter(|hz| *hz > 0);
+        self.sync_tracks();
+        if self.sink.empty() || !self.is_seekable() {
+            return;
+        }
+
+        if let Ok(elapsed) = self.elapsed() {
+            let _ = self.seek(elapsed);
+        }
+    }
+
+    /// Registers a callback that is invoked every time a track plays through to the end. The
+    /// callback runs on the audio thread, so it should hand off any heavy work.
+    pub fn on_finished(&mut self, callback: FinishedCallback) {
+        *self.on_finished.lock().unwrap_or_else(PoisonError::into_inner) = Some(callback);
+    }
+
+    /// Registers a callback that is invoked once the output device goes away, e.g. to tell the
+    /// user their headphones were unplugged. It runs on the thread that watches the device, within
+    /// about a second of the device going away, and the player is in `PlaybackState::DeviceLost`
+    /// by then.
+    pub fn on_device_lost(&mut self, callback: DeviceLostCallback) {
+        *self.on_device_lost.lock().unwrap_or_else(PoisonError::into_inner) = Some(callback);
+    }
+
+    pub fn repeat_mode(&self) -> RepeatMode {
+        *self.repeat_mode.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Sets whether the loaded track starts over once it ends. The decoded source is rebuilt from
+    /// the same file handle, so the seek index and duration stay valid across repeats.
+    pub fn set_repeat_mode(&mut self, mode: RepeatMode) {
+        *self.repeat_mode.lock().unwrap_or_else(PoisonError::into_inner) = mode;
+    }
+
+    pub fn loop_gap(&self) -> Duration {
+        self.loop_gap
+    }
+
+    /// Plays `gap` of silence every time a repeat or an A-B loop starts over, e.g. for a pause
+    /// between the repeats of an ambient track. Zero, the default, loops without a gap. The
+    /// position stays at the start of the next iteration while the gap plays, and the gap
+    /// applies from the next time a track starts over.
+    pub fn set_loop_gap(&mut self, gap: Duration) {
+        self.loop_gap = gap;
+        for track in &self.tracks {
+            track.clock.set_loop_gap(gap);
+        }
+    }
+}
+
+impl AudioPlayer for Player {
+    fn play(&mut self) -> PlayerResult<()> {
+        Player::play(self)
+    }
+
+    fn pause(&mut self) {
+        Player::pause(self);
+    }
+
+    fn stop(&mut self) {
+        Player::stop(self);
+    }
+
+    fn seek(&mut self, time_offset: Duration) -> PlayerResult<()> {
+        Player::seek(self, time_offset)
+    }
+
+    fn volume(&self) -> f32 {
+        Player::volume(self)
+    }
+
+    fn set_volume(&mut self, volume: f32) {
+        Player::set_volume(self, volume);
+    }
+
+    fn set_master_volume(&mut self, volume: f32) {
+        Player::set_master_volume(self, volume);
+    }
+
+    fn fade_in(&mut self, duration: Duration) -> PlayerResult<()> {
+        Player::fade_in(self, duration)
+    }
+
+    fn fade_out(&mut self, duration: Duration, pause: bool) {
+        Player::fade_out(self, duration, pause);
+    }
+
+    fn elapsed(&self) -> PlayerResult<Duration> {
+        Player::elapsed(self)
+    }
+
+    fn duration(&self) -> Option<Duration> {
+        Player::duration(self)
+    }
+
+    fn state(&self) -> PlaybackState {
+        Player::state(self)
+    }
+}
+
+impl Drop for Player {
+    /// Cutting the output off in the middle of a waveform can click, so a playing player is
+    /// quickly ramped down to silence before the sink is stopped, and the stream is given one more
+    /// step to flush that silence before it closes. This blocks for about 35 ms at most.
+    fn drop(&mut self) {
+        self.cancel_fade();
+        self.clear_sleep_timer();
+        self.progress_cancel.cancel();
+
+        let step = DROP_FADE / DROP_FADE_STEPS;
+        let playing = !self.sink.is_paused() && !self.sink.empty();
+        if playing {
+            let volume = self.sink.volume();
+            for remaining in (0..DROP_FADE_STEPS).rev() {
+                self.sink.set_volume(volume * remaining as f32 / DROP_FADE_STEPS as f32);
+                std::thread::sleep(step);
+            }
+        }
+
+        self.sink.stop();
+        *self.now_playing.lock().unwrap_or_else(PoisonError::into_inner) = None;
+        if playing {
+            std::thread::sleep(step);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{self, MP3_FRAME_DURATION};
+
+    /// Loads `seconds` of silent MP3 into a paused player. Paused, the sink doesn't pull any
+    /// samples, so the position only moves when the tests move it.
+    async fn paused_player(name: &str, seconds: u32) -> Option<Player> {
+        let mut player = fixtures::output_player()?;
+        player.pause();
+        let path = fixtures::silent_mp3(name, seconds);
+        player.load_path(path.to_str().unwrap()).await.unwrap();
+        Some(player)
+    }
+
+    #[tokio::test]
+    async fn seeking_moves_elapsed_to_the_target() {
+        let Some(mut player) = paused_player("seek", 20).await else {
+            return;
+        };
+        assert!(player.is_seekable());
+
+        player.seek(Duration::from_secs(10)).unwrap();
+
+        // Seeks land on the start of the frame the target is in.
+        let elapsed = player.elapsed().unwrap();
+        assert!(elapsed <= Duration::from_secs(10), "{elapsed:?}");
+        assert!(Duration::from_secs(10) - elapsed < MP3_FRAME_DURATION, "{elapsed:?}");
+    }
+
+    #[tokio::test]
+    async fn fading_in_starts_from_the_start_position() {
+        let Some(mut player) = paused_player("fade-in-start", 10).await else {
+            return;
+        };
+        player.set_start_position(Duration::from_secs(5));
+
+        player.fade_in(Duration::from_millis(100)).unwrap();
+
+        // The player may have moved on since, but never back to the start of the track.
+        let elapsed = player.elapsed().unwrap();
+        assert!(elapsed + MP3_FRAME_DURATION >= Duration::from_secs(5), "{elapsed:?}");
+        assert_eq!(player.start_position, None);
+    }
+
+    #[tokio::test]
+    async fn skipping_drops_exactly_one_track() {
+        let Some(mut player) = paused_player("skip-first", 5).await else {
+            return;
+        };
+        for name in ["skip-second", "skip-third"] {
+            let path = fixtures::silent_mp3(name, 5);
+            player.enqueue_path(path.to_str().unwrap()).await.unwrap();
+        }
+        assert_eq!(player.queue_len(), 3);
+
+        player.skip();
+
+        assert_eq!(player.queue_len(), 2);
+        assert!(player.path().unwrap().ends_with("skip-second.mp3"), "{:?}", player.path());
+    }
+
+    #[tokio::test]
+    async fn progress_subscriptions_end_with_the_output() {
+        let Some(mut player) = paused_player("progress-end", 5).await else {
+            return;
+        };
+        // A paused player sends nothing, so the receivers only return once their sender is gone.
+        let mut switched = player.subscribe_progress(Duration::from_millis(10));
+        player.recover_device().unwrap();
+        let mut dropped = player.subscribe_progress(Duration::from_millis(10));
+        // The fade's thread holds on to the sink, which mustn't keep the subscription going.
+        player.fade_out(Duration::from_secs(10), true);
+        drop(player);
+
+        for receiver in [&mut switched, &mut dropped] {
+            let received = tokio::time::timeout(Duration::from_secs(1), receiver.recv()).await;
+            assert_eq!(received, Ok(None));
+        }
+    }
+
+    #[test]
+    fn progress_subscriptions_off_the_runtime_end_with_the_player() {
+        let Some(player) = fixtures::output_player() else {
+            return;
+        };
+        let mut receiver = player.subscribe_progress(Duration::from_millis(10));
+        drop(player);
+
+        let deadline = Instant::now() + Duration::from_secs(1);
+        while receiver.try_recv() != Err(tokio::sync::mpsc::error::TryRecvError::Disconnected) {
+            assert!(Instant::now() < deadline, "the subscription outlived the player");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// Compares the seek index lookups against the linear scan they replaced, on the index of a
+    /// track about 7 hours long. Run with `cargo test --release -- --ignored --nocapture`.
+    #[test]
+    #[ignore = "a timing comparison, only meaningful in release builds"]
+    fn binary_searching_the_seek_index_beats_a_linear_scan() {
+        let seek_index: Vec<(Duration, u64)> = (0..1_000_000u64)
+            .map(|frame| (MP3_FRAME_DURATION * frame as u32, frame * 417))
+            .collect();
+        let end = seek_index.last().unwrap().0;
+        let targets: Vec<Duration> = (0..1_000u32).map(|step| end / 1_000 * step).collect();
+
+        let linear_offset = |time: Duration| {
+            let mut offset = 0;
+            for (frame_time, frame_offset) in &seek_index {
+                if *frame_time > time {
+                    break;
+                }
+                offset = *frame_offset;
+            }
+            offset
+        };
+        let linear_time = |offset: u64| {
+            let mut time = Duration::ZERO;
+            for (frame_time, frame_offset) in &seek_index {
+                if *frame_offset > offset {
+                    break;
+                }
+                time = *frame_time;
+            }
+            time
+        };
+
+        let started = Instant::now();
+        let linear: Vec<(u64, Duration)> = targets
+            .iter()
+            .map(|time| std::hint::black_box(linear_offset(*time)))
+            .map(|offset| (offset, linear_time(offset)))
+            .collect();
+        let linear_took = started.elapsed();
+
+        let started = Instant::now();
+        let searched: Vec<(u64, Duration)> = targets
+            .iter()
+            .map(|time| std::hint::black_box(Player::indexed_offset_for_time(&seek_index, *time)))
+            .map(|offset| (offset, Player::indexed_time_for_offset(&seek_index, offset)))
+            .collect();
+        let searched_took = started.elapsed();
+
+        println!(
+            "{} seeks: linear scan {linear_took:?}, binary search {searched_took:?}",
+            targets.len()
+        );
+        assert_eq!(searched, linear);
+        assert!(searched_took * 10 < linear_took, "{searched_took:?} vs {linear_took:?}");
+    }
+
+    #[tokio::test]
+    async fn a_lost_device_fails_instead_of_blocking() {
+        let Some(mut player) = paused_player("device-lost", 5).await else {
+            return;
+        };
+        player.stop();
+        player.output.lost.store(true, Ordering::SeqCst);
+        assert_eq!(player.state(), PlaybackState::DeviceLost);
+
+        let path = fixtures::silent_mp3("device-lost-next", 1);
+        assert_eq!(player.seek(Duration::from_secs(1)), Err(PlayerError::DeviceLost));
+        assert_eq!(player.seek_forward(Duration::from_secs(1)), Err(PlayerError::DeviceLost));
+        assert_eq!(player.play(), Err(PlayerError::DeviceLost));
+        assert_eq!(player.fade_in(Duration::from_secs(1)), Err(PlayerError::DeviceLost));
+        assert_eq!(player.restart(), Err(PlayerError::DeviceLost));
+        assert_eq!(player.load_path(path.to_str().unwrap()).await, Err(PlayerError::DeviceLost));
+        assert_eq!(player.load_bytes(Vec::new()).await, Err(PlayerError::DeviceLost));
+    }
+
+    #[tokio::test]
+    async fn switching_devices_keeps_the_sleep_timer() {
+        let Some(mut player) = paused_player("sleep-timer-switch", 5).await else {
+            return;
+        };
+        player.set_sleep_timer(Duration::from_secs(60));
+
+        player.recover_device().unwrap();
+
+        // Paused, the timer doesn't count down, so all of it is left.
+        let left = *player.sleep_timer_left.lock().unwrap();
+        assert_eq!(left, Some(Duration::from_secs(60)));
+
+        player.clear_sleep_timer();
+        assert_eq!(*player.sleep_timer_left.lock().unwrap(), None);
+    }
+
+    #[test]
+    fn a_device_player_checks_its_config() {
+        let config = PlayerConfig {
+            initial_volume: 2.0,
+            ..PlayerConfig::default()
+        };
+
+        // The config is checked first, so no device is needed, nor one by that name.
+        let result = Player::with_config_and_device(config, "no such device");
+        assert!(matches!(result, Err(PlayerError::InvalidVolume)));
+    }
+
+    #[tokio::test]
+    async fn tracks_are_prepared_without_a_player() {
+        let path = fixtures::silent_mp3("prepared", 3);
+        let prepared = TrackLoader::new(PlayerConfig::default())
+            .prepare_path(path.to_str().unwrap())
+            .await
+            .unwrap();
+
+        let Some(mut player) = fixtures::output_player() else {
+            return;
+        };
+        player.pause();
+        player.load_prepared(prepared).unwrap();
+
+        assert_eq!(player.state(), PlaybackState::Paused);
+        assert!(player.is_seekable());
+        let duration = player.duration().unwrap();
+        assert!(duration.abs_diff(Duration::from_secs(3)) < MP3_FRAME_DURATION, "{duration:?}");
+    }
+
+    #[tokio::test]
+    async fn a_non_audio_file_fails_to_load() {
+        let path = fixtures::write("notes.mp3", b"not audio at all, just some text\n");
+        let path = path.to_str().unwrap();
+
+        // Preparing is the part of loading that reads the file, and it needs no output device.
+        let error = TrackLoader::new(PlayerConfig::default()).prepare_path(path).await.err();
+        assert!(matches!(error, Some(PlayerError::UnsupportedFormat { .. })), "{error:?}");
+        assert!(Player::probe(path).is_err());
+
+        let Some(mut player) = fixtures::output_player() else {
+            return;
+        };
+        let file = tokio::fs::File::open(path).await.unwrap();
+        assert!(player.load_file(file).await.is_err());
+        assert_eq!(player.state(), PlaybackState::Empty);
+    }
+
+    #[test]
+    fn a_master_volume_change_during_a_fade_is_kept() {
+        let Some(mut player) = fixtures::output_player() else {
+            return;
+        };
+        player.set_volume(0.8);
+        player.fade_in(Duration::from_millis(200)).unwrap();
+
+        std::thread::sleep(Duration::from_millis(50));
+        player.set_master_volume(0.5);
+        std::thread::sleep(Duration::from_millis(400));
+
+        // The fade ends at the player's volume, scaled by the master volume it was changed to.
+        let expected = player.volume_curve.amplitude(0.8 * 0.5);
+        let volume = player.sink.volume();
+        assert!((volume - expected).abs() < 1e-6, "expected {expected}, got {volume}");
+    }
+
+    #[tokio::test]
+    async fn extreme_durations_are_clamped_instead_of_overflowing() {
+        let Some(mut player) = paused_player("extreme", 5).await else {
+            return;
+        };
+        let duration = player.duration().unwrap();
+
+        player.seek(Duration::MAX).unwrap();
+        assert!(player.elapsed().unwrap() <= duration);
+        player.seek_forward(Duration::MAX).unwrap();
+        assert!(player.elapsed().unwrap() <= duration);
+        player.seek_backward(Duration::MAX).unwrap();
+        assert!(player.elapsed().unwrap() <= MP3_FRAME_DURATION);
+        player.seek_percent(1.0).unwrap();
+        assert!(player.elapsed().unwrap() <= duration);
+
+        assert_eq!(
+            player.set_ab_loop(Duration::ZERO, Duration::MAX),
+            Err(PlayerError::InvalidLoop)
+        );
+        assert_eq!(
+            player.set_ab_loop(Duration::MAX, Duration::MAX),
+            Err(PlayerError::InvalidLoop)
+        );
+
+        player.set_sleep_timer(Duration::MAX);
+        player.clear_sleep_timer();
+
+        player.append_silence(Duration::MAX);
+        assert_eq!(player.queue_len(), 2);
+    }
+
+    #[test]
+    fn thinning_by_the_largest_granularity_doesnt_overflow() {
+        let seek_index = vec![(Duration::ZERO, 0), (Duration::from_secs(1), 417), (Duration::MAX, 834)];
+
+        // The next entry kept has to be at least `Duration::MAX` in, which only the last one is.
+        let thinned = Player::thin_seek_index(seek_index, Duration::MAX);
+        assert_eq!(thinned, vec![(Duration::ZERO, 0), (Duration::MAX, 834)]);
+    }
+
+    #[tokio::test]
+    async fn the_waveform_of_silence_is_flat() {
+        let Some(player) = paused_player("waveform", 2).await else {
+            return;
+        };
+
+        let peaks = player.compute_waveform(16).await.unwrap();
+        assert_eq!(peaks, vec![0.0; 16]);
+    }
+
+    #[tokio::test]
+    async fn normalizing_audio_in_memory_measures_it_off_the_async_thread() {
+        let Some(mut player) = fixtures::output_player() else {
+            return;
+        };
+        player.pause();
+        let data = std::fs::read(fixtures::silent_mp3("normalized-bytes", 2)).unwrap();
+        player.load_bytes(data).await.unwrap();
+
+        player.enable_normalization(-14.0).await;
+
+        // Silence has no level to measure, so it plays at its own level.
+        let track = player.current_track().unwrap();
+        assert_eq!(track.loudness, None);
+        assert_eq!(f32::from_bits(track.gain.load(Ordering::SeqCst)), 1.0);
+    }
+
+    /// How far a trimmed duration can be from the tone, which is trimmed to whole waveform chunks.
+    fn assert_trimmed_to(player: &Player, expected: Duration, sample_rate: u32) {
+        let chunk = Player::sample_time(WAVEFORM_CHUNK_FRAMES as u64, sample_rate);
+        let duration = player.duration().unwrap();
+        assert!(
+            duration.abs_diff(expected) <= chunk,
+            "expected about {expected:?}, got {duration:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn silence_is_trimmed_by_the_returned_future() {
+        let Some(mut player) = fixtures::output_player() else {
+            return;
+        };
+        player.pause();
+        let path = fixtures::tone_wav("trim", 1, 8000);
+        player.load_path(path.to_str().unwrap()).await.unwrap();
+
+        let trims = player.set_skip_silence(true);
+        // Nothing is decoded until the future runs, so the player stays free until then.
+        assert_trimmed_to(&player, Duration::from_secs(3), 8000);
+        trims.await;
+        assert_trimmed_to(&player, Duration::from_secs(1), 8000);
+
+        // A threshold that was replaced, here by turning skipping off, doesn't trim anything.
+        let stale = player.set_silence_threshold(-60.0);
+        player.set_skip_silence(false).await;
+        stale.await;
+        assert_trimmed_to(&player, Duration::from_secs(3), 8000);
+    }
+
+    #[tokio::test]
+    async fn a_wav_loaded_by_path_gets_its_duration_from_the_tags() {
+        let Some(mut player) = fixtures::output_player() else {
+            return;
+        };
+        player.pause();
+        let path = fixtures::tone_wav("wav-duration", 1, 8000);
+        player.load_path(path.to_str().unwrap()).await.unwrap();
+
+        assert_eq!(player.duration(), Some(Duration::from_secs(3)));
+    }
+
+    #[tokio::test]
+    async fn setting_the_same_silence_threshold_does_nothing() {
+        let Some(mut player) = fixtures::output_player() else {
+            return;
+        };
+        player.pause();
+        let path = fixtures::tone_wav("same-threshold", 1, 8000);
+        player.load_path(path.to_str().unwrap()).await.unwrap();
+        player.set_skip_silence(true).await;
+        let generation = player.trim_generation.load(Ordering::SeqCst);
+
+        player.set_silence_threshold(player.silence_threshold()).await;
+        assert_eq!(player.trim_generation.load(Ordering::SeqCst), generation);
+
+        player.set_silence_threshold(f32::NAN).await;
+        assert_eq!(player.silence_threshold(), DEFAULT_SILENCE_THRESHOLD_DB);
+        assert_eq!(player.trim_generation.load(Ordering::SeqCst), generation);
+        assert_trimmed_to(&player, Duration::from_secs(1), 8000);
     }
 }
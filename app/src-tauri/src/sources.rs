@@ -0,0 +1,1144 @@
+use std::{
+    io::Seek,
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering},
+        Arc, Mutex, PoisonError,
+    },
+    time::Duration,
+};
+
+use rodio::{source::SeekError, Source};
+
+use crate::decoder::{AudioDecoder, DecoderSettings};
+use crate::equalizer::{EqualizerFilters, SharedEqualizer};
+use crate::media::MediaHandle;
+use crate::player::RepeatMode;
+
+/// How much audio each level measurement covers. Short enough for a VU display to look live, long
+/// enough to catch the peaks in between two reads.
+const LEVEL_WINDOW: Duration = Duration::from_millis(50);
+
+/// Marks an A-B loop as unset in `TrackClock::loop_end`.
+const NO_LOOP: u64 = u64::MAX;
+
+/// Marks the end of the track as untrimmed in `TrackClock::trim_end`.
+const NO_TRIM: u64 = u64::MAX;
+
+/// How far playback has got into one track, and how long the track is when that's known. It also
+/// holds the A-B loop of the track, which its source jumps back to the start of, and the silence
+/// trimmed off its ends, which its source skips over.
+///
+/// With silence trimmed, `elapsed()` and `duration()` leave it out, so the track seems to start
+/// where the sound does. Everything else is in the time of the untrimmed track.
+pub struct TrackClock {
+    /// Nanoseconds into the track, kept up to date by the track's source.
+    position: AtomicU64,
+    duration: Option<Duration>,
+    /// Where the A-B loop ends in nanoseconds, or `NO_LOOP`. This is checked for every sample, so
+    /// it's kept apart from the start, which is only needed once the end is reached.
+    loop_end: AtomicU64,
+    /// The byte offset and time of the frame the A-B loop jumps back to.
+    loop_start: Mutex<(u64, Duration)>,
+    /// How many samples the next source created for the track drops before playing anything.
+    pending_skip: AtomicU64,
+    /// Where the sound starts and ends in nanoseconds, once silence has been trimmed. The end is
+    /// `NO_TRIM` until then.
+    trim_start: AtomicU64,
+    trim_end: AtomicU64,
+    /// How many nanoseconds of silence play before every repeat or A-B loop starts over.
+    loop_gap: AtomicU64,
+}
+
+impl TrackClock {
+    pub fn new(duration: Option<Duration>) -> Self {
+        Self {
+            position: AtomicU64::new(0),
+            duration,
+            loop_end: AtomicU64::new(NO_LOOP),
+            loop_start: Mutex::new((0, Duration::ZERO)),
+            pending_skip: AtomicU64::new(0),
+            trim_start: AtomicU64::new(0),
+            trim_end: AtomicU64::new(NO_TRIM),
+            loop_gap: AtomicU64::new(0),
+        }
+    }
+
+    /// Makes the next source created for the track drop its first `samples` samples, counting
+    /// every channel, to start at an exact sample rather than at the start of a frame.
+    pub fn skip_on_start(&self, samples: u64) {
+        self.pending_skip.store(samples, Ordering::SeqCst);
+    }
+
+    /// Loops playback between the frame at `start_offset`, which begins at `start_time`, and
+    /// `end`.
+    pub fn set_loop(&self, start_offset: u64, start_time: Duration, end: Duration) {
+        *self.loop_start.lock().unwrap_or_else(PoisonError::into_inner) = (start_offset, start_time);
+        let end = u64::try_from(end.as_nanos()).unwrap_or(NO_LOOP - 1);
+        self.loop_end.store(end, Ordering::SeqCst);
+    }
+
+    pub fn clear_loop(&self) {
+        self.loop_end.store(NO_LOOP, Ordering::SeqCst);
+    }
+
+    pub fn set_loop_gap(&self, gap: Duration) {
+        let gap = u64::try_from(gap.as_nanos()).unwrap_or(u64::MAX);
+        self.loop_gap.store(gap, Ordering::SeqCst);
+    }
+
+    /// The frame to jump back to, if there is a loop and `position` has reached its end. Passing
+    /// `None` asks for the loop start regardless of the position, for when the track runs out.
+    fn loop_start(&self, position: Option<u64>) -> Option<(u64, Duration)> {
+        let end = self.loop_end.load(Ordering::SeqCst);
+        if end == NO_LOOP || position.is_some_and(|position| position < end) {
+            return None;
+        }
+
+        Some(*self.loop_start.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    /// Skips playing the parts of the track before `start` and after `end`.
+    pub fn set_trim(&self, start: Duration, end: Duration) {
+        let end = u64::try_from(end.as_nanos()).unwrap_or(NO_TRIM - 1);
+        let start = u64::try_from(start.as_nanos()).unwrap_or(end).min(end);
+        self.trim_start.store(start, Ordering::SeqCst);
+        self.trim_end.store(end, Ordering::SeqCst);
+    }
+
+    pub fn clear_trim(&self) {
+        self.trim_end.store(NO_TRIM, Ordering::SeqCst);
+        self.trim_start.store(0, Ordering::SeqCst);
+    }
+
+    /// Copies the trimmed silence over from the clock of the same track in another player.
+    pub fn copy_trim(&self, from: &TrackClock) {
+        self.trim_start
+            .store(from.trim_start.load(Ordering::SeqCst), Ordering::SeqCst);
+        self.trim_end.store(from.trim_end.load(Ordering::SeqCst), Ordering::SeqCst);
+    }
+
+    fn trim(&self) -> (u64, u64) {
+        (self.trim_start.load(Ordering::SeqCst), self.trim_end.load(Ordering::SeqCst))
+    }
+
+    /// How far into the trimmed track playback is.
+    pub fn elapsed(&self) -> Duration {
+        let (start, end) = self.trim();
+        let position = self.position.load(Ordering::SeqCst).min(end);
+        Duration::from_nanos(position.saturating_sub(start))
+    }
+
+    /// How long the trimmed track is.
+    pub fn duration(&self) -> Option<Duration> {
+        match self.trim() {
+            (_, NO_TRIM) => self.duration,
+            (start, end) => {
+                let end = self
+                    .duration
+                    .map_or(end, |duration| end.min(u64::try_from(duration.as_nanos()).unwrap_or(end)));
+                Some(Duration::from_nanos(end.saturating_sub(start)))
+            }
+        }
+    }
+
+    /// How long the untrimmed track is.
+    pub fn track_duration(&self) -> Option<Duration> {
+        self.duration
+    }
+
+    /// How far into the untrimmed track playback is.
+    pub fn track_position(&self) -> Duration {
+        Duration::from_nanos(self.position.load(Ordering::SeqCst))
+    }
+
+    /// Turns a time in the trimmed track, like the ones `elapsed()` gives, into the time in the
+    /// untrimmed track that seeks and loops work with.
+    pub fn track_time(&self, time: Duration) -> Duration {
+        time.saturating_add(Duration::from_nanos(self.trim().0))
+    }
+
+    /// Moves the position back to the start, for a track that has stopped playing.
+    pub fn rewind(&self) {
+        self.position.store(0, Ordering::SeqCst);
+    }
+}
+
+/// The clock of whichever track the sink is playing. Each source puts its clock here when it
+/// starts, so the current track can be followed from outside the player.
+pub type NowPlaying = Arc<Mutex<Option<Arc<TrackClock>>>>;
+
+/// Decodes the loaded audio and starts it over from the beginning whenever it runs out, for as
+/// long as the shared repeat mode asks for it. While the track has an A-B loop it jumps back to
+/// the start of the loop instead, once it reaches the end of the loop or of the track. Each time
+/// it starts over it can play the loop gap of the track's clock first.
+///
+/// It also keeps count of the samples it has handed on, and publishes how far into the track that
+/// is to the track's clock. The sink stops pulling samples while paused and pulls them faster
+/// when sped up, so this follows what is actually being heard.
+pub struct Repeating {
+    decoder: AudioDecoder,
+    file_handle: MediaHandle,
+    /// What the decoders it creates to start over are opened with.
+    decoder_settings: DecoderSettings,
+    repeat_mode: Arc<Mutex<RepeatMode>>,
+    /// Where in the track the decoder started decoding.
+    start: Duration,
+    played_samples: u64,
+    /// Samples still to be dropped before the first one is played. They already count as played,
+    /// so the clock is at the right position from the start.
+    skip: u64,
+    /// Samples of silence still to be played before the decoder carries on after starting over.
+    gap: u64,
+    clock: Arc<TrackClock>,
+    now_playing: NowPlaying,
+    started: bool,
+}
+
+impl Repeating {
+    pub fn new(
+        decoder: AudioDecoder,
+        file_handle: MediaHandle,
+        decoder_settings: DecoderSettings,
+        repeat_mode: Arc<Mutex<RepeatMode>>,
+        start: Duration,
+        clock: Arc<TrackClock>,
+        now_playing: NowPlaying,
+    ) -> Self {
+        let mut skip = clock.pending_skip.swap(0, Ordering::SeqCst);
+        if start.is_zero() {
+            skip = skip.max(Self::lead_skip(&decoder, &clock));
+        }
+        let source = Self {
+            decoder,
+            file_handle,
+            decoder_settings,
+            repeat_mode,
+            start,
+            played_samples: skip,
+            skip,
+            gap: 0,
+            clock,
+            now_playing,
+            started: false,
+        };
+        source.publish_position();
+        source
+    }
+
+    /// How many samples the trimmed silence at the start of the track takes up, for a source that
+    /// starts at the beginning. They are decoded and dropped rather than seeked past, which
+    /// delays the start a little for long stretches of silence, but works without a seek index.
+    fn lead_skip(decoder: &AudioDecoder, clock: &TrackClock) -> u64 {
+        let frames = u128::from(clock.trim().0) * u128::from(decoder.sample_rate()) / 1_000_000_000;
+        u64::try_from(frames * u128::from(decoder.channels())).unwrap_or(u64::MAX)
+    }
+
+    /// How many samples of silence the loop gap takes up.
+    fn gap_samples(&self) -> u64 {
+        let frames =
+            u128::from(self.clock.loop_gap.load(Ordering::SeqCst)) * u128::from(self.decoder.sample_rate()) / 1_000_000_000;
+        u64::try_from(frames * u128::from(self.decoder.channels())).unwrap_or(u64::MAX)
+    }
+
+    /// Plays one sample of the silence left in the loop gap, if there is any.
+    fn gap_sample(&mut self) -> Option<i16> {
+        if self.gap == 0 {
+            return None;
+        }
+        self.gap -= 1;
+        Some(0)
+    }
+
+    /// Drops the samples the source was asked to skip before playing anything.
+    fn skip_samples(&mut self) -> Option<()> {
+        for _ in 0..std::mem::take(&mut self.skip) {
+            self.decoder.next()?;
+        }
+        Some(())
+    }
+
+    /// Whether playback has reached the trimmed silence at the end of the track.
+    fn past_trim_end(&self) -> bool {
+        self.clock.position.load(Ordering::SeqCst) >= self.clock.trim().1
+    }
+
+    /// Stores the position in the clock and returns it, in nanoseconds.
+    fn publish_position(&self) -> Option<u64> {
+        let samples_per_second = u128::from(self.decoder.sample_rate()) * u128::from(self.decoder.channels());
+        if samples_per_second == 0 {
+            return None;
+        }
+
+        let played = u128::from(self.played_samples) * 1_000_000_000 / samples_per_second;
+        let nanos = u64::try_from(self.start.as_nanos() + played).unwrap_or(u64::MAX);
+        self.clock.position.store(nanos, Ordering::SeqCst);
+        Some(nanos)
+    }
+
+    /// Moves the handle to the frame at `offset`, which begins at `time`, and swaps in a fresh
+    /// decoder that starts from there.
+    fn restart_at(&mut self, offset: u64, time: Duration) -> bool {
+        let mut file_handle = match self.file_handle.try_clone() {
+            Ok(file_handle) => file_handle,
+            Err(_) => return false,
+        };
+
+        if file_handle.seek(std::io::SeekFrom::Start(offset)).is_err() {
+            return false;
+        }
+
+        match AudioDecoder::open_at(file_handle, self.decoder_settings, time) {
+            Ok(decoder) => {
+                self.skip = match time.is_zero() {
+                    true => Self::lead_skip(&decoder, &self.clock),
+                    false => 0,
+                };
+                self.decoder = decoder;
+                self.start = time;
+                self.played_samples = self.skip;
+                self.gap = self.gap_samples();
+                self.publish_position();
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Starts over once the decoder has run out, at the start of the A-B loop if there is one and
+    /// otherwise at the start of the track if the repeat mode asks for it.
+    fn restart_after_end(&mut self) -> bool {
+        if let Some((offset, time)) = self.clock.loop_start(None) {
+            return self.restart_at(offset, time);
+        }
+
+        let repeat_mode = *self.repeat_mode.lock().unwrap_or_else(PoisonError::into_inner);
+        repeat_mode != RepeatMode::Off && self.restart_at(0, Duration::ZERO)
+    }
+}
+
+impl Iterator for Repeating {
+    type Item = i16;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(silence) = self.gap_sample() {
+            return Some(silence);
+        }
+
+        self.skip_samples()?;
+        let sample = match self.decoder.next().filter(|_| !self.past_trim_end()) {
+            Some(sample) => sample,
+            None => {
+                if !self.restart_after_end() {
+                    return None;
+                }
+                if let Some(silence) = self.gap_sample() {
+                    return Some(silence);
+                }
+                self.skip_samples()?;
+                self.decoder.next()?
+            }
+        };
+
+        if !self.started {
+            self.started = true;
+            *self.now_playing.lock().unwrap_or_else(PoisonError::into_inner) = Some(Arc::clone(&self.clock));
+        }
+
+        self.played_samples += 1;
+        let position = self.publish_position();
+        if let Some((offset, time)) = position.and_then(|position| self.clock.loop_start(Some(position))) {
+            self.restart_at(offset, time);
+        }
+
+        Some(sample)
+    }
+}
+
+impl Source for Repeating {
+    /// The loop gap counts as a frame of its own, in the format of the decoder it comes before.
+    fn current_frame_len(&self) -> Option<usize> {
+        match self.gap {
+            0 => self.decoder.current_frame_len(),
+            gap => Some(usize::try_from(gap).unwrap_or(usize::MAX)),
+        }
+    }
+
+    fn channels(&self) -> u16 {
+        self.decoder.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.decoder.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        match *self.repeat_mode.lock().unwrap_or_else(PoisonError::into_inner) {
+            RepeatMode::Off => self.decoder.total_duration(),
+            _ => None,
+        }
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.decoder.try_seek(pos)?;
+        self.start = pos;
+        self.played_samples = 0;
+        self.skip = 0;
+        self.publish_position();
+        Ok(())
+    }
+}
+
+/// Plays silence for as long as the duration of its clock, for a gap queued between tracks. It
+/// counts its position up on the clock like `Repeating` does, so the gap can be followed from
+/// outside the player the same way as a track.
+pub struct Silence {
+    sample_rate: u32,
+    channels: u16,
+    /// Where in the gap the source started.
+    start: Duration,
+    played_samples: u64,
+    /// How many samples, counting every channel, there are from `start` to the end of the gap.
+    samples: u64,
+    clock: Arc<TrackClock>,
+    now_playing: NowPlaying,
+    started: bool,
+}
+
+impl Silence {
+    pub fn new(sample_rate: u32, channels: u16, start: Duration, clock: Arc<TrackClock>, now_playing: NowPlaying) -> Self {
+        let mut source = Self {
+            sample_rate,
+            channels,
+            start,
+            played_samples: 0,
+            samples: 0,
+            clock,
+            now_playing,
+            started: false,
+        };
+        source.seek_to(start);
+        source
+    }
+
+    fn seek_to(&mut self, start: Duration) {
+        let remaining = self.clock.duration.unwrap_or_default().saturating_sub(start);
+        let frames = remaining.as_nanos() * u128::from(self.sample_rate) / 1_000_000_000;
+        self.samples = u64::try_from(frames * u128::from(self.channels)).unwrap_or(u64::MAX);
+        self.start = start;
+        self.played_samples = 0;
+        self.publish_position();
+    }
+
+    fn publish_position(&self) {
+        let samples_per_second = u128::from(self.sample_rate) * u128::from(self.channels);
+        if samples_per_second == 0 {
+            return;
+        }
+
+        let played = u128::from(self.played_samples) * 1_000_000_000 / samples_per_second;
+        let nanos = u64::try_from(self.start.as_nanos() + played).unwrap_or(u64::MAX);
+        self.clock.position.store(nanos, Ordering::SeqCst);
+    }
+}
+
+impl Iterator for Silence {
+    type Item = i16;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.played_samples >= self.samples {
+            return None;
+        }
+
+        if !self.started {
+            self.started = true;
+            *self.now_playing.lock().unwrap_or_else(PoisonError::into_inner) = Some(Arc::clone(&self.clock));
+        }
+
+        self.played_samples += 1;
+        self.publish_position();
+        Some(0)
+    }
+}
+
+impl Source for Silence {
+    fn current_frame_len(&self) -> Option<usize> {
+        Some(usize::try_from(self.samples - self.played_samples).unwrap_or(usize::MAX))
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.clock.duration.map(|duration| duration.saturating_sub(self.start))
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.seek_to(pos);
+        Ok(())
+    }
+}
+
+/// Runs the samples of `input` through the player's equalizer. While the equalizer is flat the
+/// samples are passed through untouched.
+pub struct Equalized<S> {
+    input: S,
+    equalizer: Arc<SharedEqualizer>,
+    /// The equalizer generation and format the filters were built for.
+    generation: Option<u64>,
+    format: (u32, u16),
+    filters: Option<EqualizerFilters>,
+    channel: u16,
+}
+
+impl<S: Source<Item = i16>> Equalized<S> {
+    pub fn new(input: S, equalizer: Arc<SharedEqualizer>) -> Self {
+        Self {
+            input,
+            equalizer,
+            generation: None,
+            format: (0, 0),
+            filters: None,
+            channel: 0,
+        }
+    }
+
+    /// Rebuilds the filters if the equalizer or the format of the input changed since they were
+    /// last built. Only called at the start of a frame, so every channel switches over together.
+    fn refresh_filters(&mut self) {
+        let generation = self.equalizer.generation();
+        let format = (self.input.sample_rate(), self.input.channels());
+        if self.generation == Some(generation) && self.format == format {
+            return;
+        }
+
+        self.generation = Some(generation);
+        self.format = format;
+        self.filters = EqualizerFilters::new(&self.equalizer.get(), format.0, format.1);
+    }
+}
+
+impl<S: Source<Item = i16>> Iterator for Equalized<S> {
+    type Item = i16;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.channel == 0 {
+            self.refresh_filters();
+        }
+
+        let sample = self.input.next()?;
+        let channel = self.channel;
+        self.channel = (self.channel + 1) % self.format.1.max(1);
+
+        match &mut self.filters {
+            Some(filters) => {
+                let filtered = filters.process(channel as usize, sample as f32);
+                Some(filtered.clamp(i16::MIN as f32, i16::MAX as f32) as i16)
+            }
+            None => Some(sample),
+        }
+    }
+}
+
+impl<S: Source<Item = i16>> Source for Equalized<S> {
+    fn current_frame_len(&self) -> Option<usize> {
+        self.input.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.input.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.input.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.input.try_seek(pos)?;
+        // The filter history belongs to the audio before the seek.
+        self.generation = None;
+        self.channel = 0;
+        Ok(())
+    }
+}
+
+/// The peak levels of the most recent stretch of audio a player has played, from `0.0` to `1.0`,
+/// stored as the bits of an `f32`.
+#[derive(Default)]
+pub struct Levels {
+    left: AtomicU32,
+    right: AtomicU32,
+}
+
+impl Levels {
+    pub fn get(&self) -> (f32, f32) {
+        (
+            f32::from_bits(self.left.load(Ordering::SeqCst)),
+            f32::from_bits(self.right.load(Ordering::SeqCst)),
+        )
+    }
+
+    fn set(&self, left: f32, right: f32) {
+        self.left.store(left.to_bits(), Ordering::SeqCst);
+        self.right.store(right.to_bits(), Ordering::SeqCst);
+    }
+}
+
+/// Passes the samples of `input` through unchanged while measuring their peak level, which is
+/// published to `levels` once per `LEVEL_WINDOW` of audio. Even channels count towards the left
+/// level and odd ones towards the right, and mono audio towards both.
+pub struct Metered<S> {
+    input: S,
+    levels: Arc<Levels>,
+    peaks: (f32, f32),
+    channel: u16,
+    remaining_samples: u64,
+}
+
+impl<S: Source<Item = i16>> Metered<S> {
+    pub fn new(input: S, levels: Arc<Levels>) -> Self {
+        Self {
+            input,
+            levels,
+            peaks: (0.0, 0.0),
+            channel: 0,
+            remaining_samples: 0,
+        }
+    }
+
+    fn window_samples(&self) -> u64 {
+        let samples_per_second = u64::from(self.input.sample_rate()) * u64::from(self.input.channels());
+        (samples_per_second * LEVEL_WINDOW.as_millis() as u64 / 1000).max(1)
+    }
+}
+
+impl<S: Source<Item = i16>> Iterator for Metered<S> {
+    type Item = i16;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let sample = self.input.next()?;
+        let channels = self.input.channels().max(1);
+
+        let level = (f32::from(sample) / f32::from(i16::MAX)).abs().min(1.0);
+        let left = self.channel.is_multiple_of(2);
+        if channels == 1 || left {
+            self.peaks.0 = self.peaks.0.max(level);
+        }
+        if channels == 1 || !left {
+            self.peaks.1 = self.peaks.1.max(level);
+        }
+        self.channel = (self.channel + 1) % channels;
+
+        if self.remaining_samples <= 1 {
+            self.levels.set(self.peaks.0, self.peaks.1);
+            self.peaks = (0.0, 0.0);
+            self.remaining_samples = self.window_samples();
+        } else {
+            self.remaining_samples -= 1;
+        }
+
+        Some(sample)
+    }
+}
+
+impl<S: Source<Item = i16>> Source for Metered<S> {
+    fn current_frame_len(&self) -> Option<usize> {
+        self.input.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.input.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.input.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.input.try_seek(pos)?;
+        self.channel = 0;
+        Ok(())
+    }
+}
+
+/// Plays mono `input` as stereo by sending every sample to both channels, when enabled. Anything
+/// else is passed through untouched.
+pub struct Upmixed<S> {
+    input: S,
+    enabled: bool,
+    /// The right copy of the last mono sample, returned on the next call.
+    pending_right: Option<i16>,
+}
+
+impl<S: Source<Item = i16>> Upmixed<S> {
+    pub fn new(input: S, enabled: bool) -> Self {
+        Self {
+            input,
+            enabled,
+            pending_right: None,
+        }
+    }
+
+    fn is_upmixing(&self) -> bool {
+        self.enabled && self.input.channels() == 1
+    }
+}
+
+impl<S: Source<Item = i16>> Iterator for Upmixed<S> {
+    type Item = i16;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(right) = self.pending_right.take() {
+            return Some(right);
+        }
+
+        let sample = self.input.next()?;
+        if self.is_upmixing() {
+            self.pending_right = Some(sample);
+        }
+        Some(sample)
+    }
+}
+
+impl<S: Source<Item = i16>> Source for Upmixed<S> {
+    fn current_frame_len(&self) -> Option<usize> {
+        match self.is_upmixing() {
+            true => self
+                .input
+                .current_frame_len()
+                .map(|len| len * 2 + usize::from(self.pending_right.is_some())),
+            false => self.input.current_frame_len(),
+        }
+    }
+
+    fn channels(&self) -> u16 {
+        match self.is_upmixing() {
+            true => 2,
+            false => self.input.channels(),
+        }
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.input.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.input.try_seek(pos)?;
+        self.pending_right = None;
+        Ok(())
+    }
+}
+
+/// Resamples `input` to a fixed sample rate by linear interpolation between neighbouring frames,
+/// or passes it through as it is without a target rate or when it's already at that rate.
+///
+/// Linear interpolation costs a multiply and an add per sample, so it never shows up next to
+/// decoding, but it doesn't filter anything out first. Downsampling folds whatever is above the
+/// new Nyquist frequency back down as faint aliasing, and upsampling dulls the very top end a
+/// little, which is fine for mixing players but not for critical listening.
+pub struct Resampled<S> {
+    input: S,
+    /// The rate to resample to, `None` when passing through.
+    target: Option<u32>,
+    /// The input frames the current output frame is between.
+    previous: Vec<i16>,
+    next: Vec<i16>,
+    /// How far the current output frame is from `previous` towards `next`, in units of one
+    /// target-rate period.
+    offset: u64,
+    /// The interpolated output frame, and which of its samples is up next.
+    frame: Vec<i16>,
+    channel: usize,
+}
+
+impl<S: Source<Item = i16>> Resampled<S> {
+    pub fn new(input: S, target: Option<u32>) -> Self {
+        let target = target.filter(|target| *target > 0 && *target != input.sample_rate());
+        Self {
+            input,
+            target,
+            previous: Vec::new(),
+            next: Vec::new(),
+            offset: 0,
+            frame: Vec::new(),
+            channel: 0,
+        }
+    }
+
+    fn read_frame(&mut self) -> Option<Vec<i16>> {
+        let channels = usize::from(self.input.channels().max(1));
+        let frame: Vec<i16> = self.input.by_ref().take(channels).collect();
+        (frame.len() == channels).then_some(frame)
+    }
+
+    /// Interpolates the next output frame, stepping through the input as far as it needs to.
+    fn interpolate(&mut self, target: u32) -> Option<()> {
+        if self.next.is_empty() {
+            self.previous = self.read_frame()?;
+            self.next = self.read_frame()?;
+        }
+
+        let target = u64::from(target);
+        while self.offset >= target {
+            self.offset -= target;
+            let next = self.read_frame()?;
+            self.previous = std::mem::replace(&mut self.next, next);
+            // The input starting over in another format shows up as frames of another length.
+            if self.previous.len() != self.next.len() {
+                self.previous.clone_from(&self.next);
+            }
+        }
+
+        let weight = self.offset as f32 / target as f32;
+        self.frame = self
+            .previous
+            .iter()
+            .zip(&self.next)
+            .map(|(previous, next)| (f32::from(*previous) + (f32::from(*next) - f32::from(*previous)) * weight) as i16)
+            .collect();
+        self.channel = 0;
+        self.offset += u64::from(self.input.sample_rate());
+        Some(())
+    }
+}
+
+impl<S: Source<Item = i16>> Iterator for Resampled<S> {
+    type Item = i16;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let target = match self.target {
+            Some(target) => target,
+            None => return self.input.next(),
+        };
+
+        if self.channel >= self.frame.len() {
+            self.interpolate(target)?;
+        }
+        let sample = self.frame[self.channel];
+        self.channel += 1;
+        Some(sample)
+    }
+}
+
+impl<S: Source<Item = i16>> Source for Resampled<S> {
+    fn current_frame_len(&self) -> Option<usize> {
+        match self.target {
+            Some(_) => None,
+            None => self.input.current_frame_len(),
+        }
+    }
+
+    fn channels(&self) -> u16 {
+        self.input.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.target.unwrap_or_else(|| self.input.sample_rate())
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.input.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.input.try_seek(pos)?;
+        self.previous.clear();
+        self.next.clear();
+        self.offset = 0;
+        self.frame.clear();
+        self.channel = 0;
+        Ok(())
+    }
+}
+
+/// Pans `input` between the left and right channel, by the balance shared as the bits of an `f32`
+/// from `-1.0`, full left, to `1.0`, full right. The side being panned away from is turned down
+/// while the other stays at full level. Mono audio is upmixed to stereo so it can be panned too.
+pub struct Panned<S> {
+    input: S,
+    balance: Arc<AtomicU32>,
+    channel: u16,
+    /// The right half of an upmixed mono sample, returned on the next call.
+    pending_right: Option<i16>,
+}
+
+impl<S: Source<Item = i16>> Panned<S> {
+    pub fn new(input: S, balance: Arc<AtomicU32>) -> Self {
+        Self {
+            input,
+            balance,
+            channel: 0,
+            pending_right: None,
+        }
+    }
+
+    fn gains(&self) -> (f32, f32) {
+        let balance = f32::from_bits(self.balance.load(Ordering::SeqCst));
+        (1.0 - balance.max(0.0), 1.0 + balance.min(0.0))
+    }
+}
+
+fn scale(sample: i16, gain: f32) -> i16 {
+    (f32::from(sample) * gain) as i16
+}
+
+impl<S: Source<Item = i16>> Iterator for Panned<S> {
+    type Item = i16;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(right) = self.pending_right.take() {
+            return Some(right);
+        }
+
+        let sample = self.input.next()?;
+        let (left_gain, right_gain) = self.gains();
+        let channels = self.input.channels();
+
+        if channels == 1 {
+            self.pending_right = Some(scale(sample, right_gain));
+            return Some(scale(sample, left_gain));
+        }
+
+        let channel = self.channel;
+        self.channel = (self.channel + 1) % channels.max(1);
+        match channel {
+            0 => Some(scale(sample, left_gain)),
+            1 => Some(scale(sample, right_gain)),
+            _ => Some(sample),
+        }
+    }
+}
+
+impl<S: Source<Item = i16>> Source for Panned<S> {
+    fn current_frame_len(&self) -> Option<usize> {
+        match self.input.channels() {
+            1 => self
+                .input
+                .current_frame_len()
+                .map(|len| len * 2 + usize::from(self.pending_right.is_some())),
+            _ => self.input.current_frame_len(),
+        }
+    }
+
+    fn channels(&self) -> u16 {
+        self.input.channels().max(2)
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.input.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.input.try_seek(pos)?;
+        self.channel = 0;
+        self.pending_right = None;
+        Ok(())
+    }
+}
+
+/// Scales the samples of `input` by a gain shared as the bits of an `f32`, which can be changed
+/// while it plays.
+pub struct Amplified<S> {
+    input: S,
+    gain: Arc<AtomicU32>,
+}
+
+impl<S: Source<Item = i16>> Amplified<S> {
+    pub fn new(input: S, gain: Arc<AtomicU32>) -> Self {
+        Self { input, gain }
+    }
+}
+
+impl<S: Source<Item = i16>> Iterator for Amplified<S> {
+    type Item = i16;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let sample = self.input.next()?;
+        let gain = f32::from_bits(self.gain.load(Ordering::SeqCst));
+        if gain == 1.0 {
+            return Some(sample);
+        }
+
+        Some((f32::from(sample) * gain).clamp(f32::from(i16::MIN), f32::from(i16::MAX)) as i16)
+    }
+}
+
+impl<S: Source<Item = i16>> Source for Amplified<S> {
+    fn current_frame_len(&self) -> Option<usize> {
+        self.input.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.input.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.input.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.input.try_seek(pos)
+    }
+}
+
+/// Passes the samples of `input` through and calls `on_end` once it runs out, but not if it's
+/// dropped before then. A track's finished signal travels along with its samples this way, so
+/// skipping the source in the sink drops both at once.
+pub struct Signalled<S> {
+    input: S,
+    on_end: Option<Box<dyn FnOnce() + Send>>,
+}
+
+impl<S: Source<Item = i16>> Signalled<S> {
+    pub fn new(input: S, on_end: Box<dyn FnOnce() + Send>) -> Self {
+        Self {
+            input,
+            on_end: Some(on_end),
+        }
+    }
+}
+
+impl<S: Source<Item = i16>> Iterator for Signalled<S> {
+    type Item = i16;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let sample = self.input.next();
+        if sample.is_none() {
+            if let Some(on_end) = self.on_end.take() {
+                on_end();
+            }
+        }
+        sample
+    }
+}
+
+impl<S: Source<Item = i16>> Source for Signalled<S> {
+    fn current_frame_len(&self) -> Option<usize> {
+        self.input.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.input.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.input.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.input.try_seek(pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{self, MP3_FRAME_DURATION};
+    use crate::player::DecoderBackend;
+
+    /// A `Repeating` source playing `seconds` of the silent MP3 fixture once, along with the clock
+    /// it publishes its position to.
+    fn repeating(name: &str, seconds: u32) -> (Repeating, Arc<TrackClock>) {
+        let path = fixtures::silent_mp3(name, seconds);
+        let media = MediaHandle::File(std::fs::File::open(path).unwrap());
+        let settings = DecoderSettings {
+            backend: DecoderBackend::Rodio,
+            read_buffer_size: 8 * 1024,
+            seeks: false,
+        };
+        let decoder = AudioDecoder::new(media.try_clone().unwrap(), settings).unwrap();
+        let clock = Arc::new(TrackClock::new(None));
+        let source = Repeating::new(
+            decoder,
+            media,
+            settings,
+            Arc::new(Mutex::new(RepeatMode::Off)),
+            Duration::ZERO,
+            Arc::clone(&clock),
+            NowPlaying::default(),
+        );
+        (source, clock)
+    }
+
+    fn assert_near(actual: Duration, expected: Duration, tolerance: Duration) {
+        let off = actual.max(expected) - actual.min(expected);
+        assert!(off <= tolerance, "expected {expected:?}, got {actual:?}");
+    }
+
+    #[test]
+    fn elapsed_counts_the_samples_played() {
+        let (mut source, clock) = repeating("elapsed", 5);
+        assert_eq!(clock.elapsed(), Duration::ZERO);
+
+        let samples_per_second = source.sample_rate() as usize * usize::from(source.channels());
+        source.by_ref().take(samples_per_second * 3 / 2).for_each(drop);
+
+        assert_near(clock.elapsed(), Duration::from_millis(1500), Duration::from_micros(50));
+    }
+
+    #[test]
+    fn elapsed_reports_media_time_when_sped_up() {
+        let (source, clock) = repeating("speed", 5);
+        // The output converts the sped up source to its own rate, like rodio's mixer does.
+        let output = rodio::source::UniformSourceIterator::<_, i16>::new(source.speed(2.0), 2, 44100);
+
+        // One second of output at twice the speed plays two seconds of the track.
+        output.take(44100 * 2).for_each(drop);
+
+        assert_near(clock.elapsed(), Duration::from_secs(2), MP3_FRAME_DURATION);
+    }
+
+    #[test]
+    fn the_end_is_signalled_once_and_only_when_played_out() {
+        let ended = Arc::new(AtomicU64::new(0));
+        let signalled = |samples: usize| {
+            let ended = Arc::clone(&ended);
+            let input = rodio::buffer::SamplesBuffer::new(1, 44100, vec![0i16; samples]);
+            Signalled::new(
+                input,
+                Box::new(move || {
+                    ended.fetch_add(1, Ordering::SeqCst);
+                }),
+            )
+        };
+
+        let mut source = signalled(10);
+        assert_eq!(source.by_ref().count(), 10);
+        assert_eq!(source.next(), None);
+        assert_eq!(ended.load(Ordering::SeqCst), 1);
+
+        // A source dropped before it ran out, like a skipped track, doesn't signal.
+        let mut source = signalled(10);
+        source.by_ref().take(5).for_each(drop);
+        drop(source);
+        assert_eq!(ended.load(Ordering::SeqCst), 1);
+    }
+}
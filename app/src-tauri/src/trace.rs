@@ -0,0 +1,39 @@
+//! Events about what the player is doing, for diagnosing problems like a file without a duration
+//! or one that can't be seeked. With the `tracing` feature they are emitted through `tracing`,
+//! along with spans around loading and seeking, and without it they compile to nothing.
+//!
+//! Every field is recorded with its `Debug` representation:
+//!
+//! ```ignore
+//! trace::warning!("couldn't read the duration", path = path, error = error);
+//! ```
+
+#[cfg(feature = "tracing")]
+macro_rules! debug {
+    ($message:literal $(, $name:ident = $value:expr)* $(,)?) => {
+        tracing::debug!($($name = ?$value,)* $message)
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! debug {
+    ($message:literal $(, $name:ident = $value:expr)* $(,)?) => {{
+        $(let _ = &$value;)*
+    }};
+}
+
+#[cfg(feature = "tracing")]
+macro_rules! warning {
+    ($message:literal $(, $name:ident = $value:expr)* $(,)?) => {
+        tracing::warn!($($name = ?$value,)* $message)
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! warning {
+    ($message:literal $(, $name:ident = $value:expr)* $(,)?) => {{
+        $(let _ = &$value;)*
+    }};
+}
+
+pub(crate) use {debug, warning};